@@ -63,6 +63,26 @@ pub(crate) fn do_alpha_blending(buffer: [u8; 4], canvas: [u8; 4]) -> [u8; 4] {
     blend_pixel_nonpremult(u32::from_le_bytes(buffer), u32::from_le_bytes(canvas)).to_le_bytes()
 }
 
+/// Multiplies the color channels of an RGBA buffer by their alpha, computing
+/// `(c * a + 127) / 255`.
+pub(crate) fn premultiply_alpha(buffer: &mut [u8]) {
+    for pixel in buffer.chunks_exact_mut(4) {
+        let alpha = u16::from(pixel[3]);
+        for channel in &mut pixel[..3] {
+            *channel = ((u16::from(*channel) * alpha + 127) / 255) as u8;
+        }
+    }
+}
+
+/// Like [`premultiply_alpha`], but for pixels packed into a `u32` as `0xAARRGGBB`
+pub(crate) fn premultiply_argb(buffer: &mut [u32]) {
+    for pixel in buffer {
+        let [alpha, r, g, b] = pixel.to_be_bytes();
+        let premultiply = |channel: u8| ((u16::from(channel) * u16::from(alpha) + 127) / 255) as u8;
+        *pixel = u32::from_be_bytes([alpha, premultiply(r), premultiply(g), premultiply(b)]);
+    }
+}
+
 /// Divides by 255, rounding to nearest (as opposed to down, like regular integer division does).
 /// TODO: cannot output 256, so the output is effecitively u8. Plumb that through the code.
 //
@@ -106,6 +126,22 @@ mod tests {
         [blend_rgb[0], blend_rgb[1], blend_rgb[2], blend_alpha]
     }
 
+    #[test]
+    fn premultiply() {
+        let mut buffer = [
+            200, 100, 3, 255, 200, 100, 3, 0, 200, 100, 3, 128, 255, 1, 2, 1,
+        ];
+        premultiply_alpha(&mut buffer);
+        assert_eq!(
+            buffer,
+            [200, 100, 3, 255, 0, 0, 0, 0, 100, 50, 2, 128, 1, 0, 0, 1]
+        );
+
+        let mut argb = [0xffc86403, 0x00c86403, 0x80c86403, 0x01ff0102];
+        premultiply_argb(&mut argb);
+        assert_eq!(argb, [0xffc86403, 0x00000000, 0x80643202, 0x01010000]);
+    }
+
     #[test]
     #[ignore] // takes too long to run on CI. Run this locally when changing the function.
     fn alpha_blending_optimization() {
@@ -4,12 +4,16 @@ use quick_error::quick_error;
 use std::collections::HashMap;
 use std::io::{self, BufRead, Cursor, Read, Seek};
 use std::num::NonZeroU16;
-use std::ops::Range;
+use std::ops::{ControlFlow, Range};
 
-use crate::extended::{self, get_alpha_predictor, read_alpha_chunk, WebPExtendedInfo};
+use crate::downscale::BlockAverager;
+use crate::extended::{self, read_alpha_chunk, AlphaChunk, WebPExtendedInfo};
+use crate::{alpha_blending, yuv};
 
 use super::lossless::LosslessDecoder;
-use super::vp8::Vp8Decoder;
+use super::vp8::{
+    Frame, FrameHeader, MacroblockCoefficients, MacroblockInfo, MacroblockQuant, Vp8Decoder,
+};
 
 quick_error! {
     /// Errors that can occur when attempting to decode a WebP image
@@ -43,9 +47,8 @@ quick_error! {
             display("Invalid Chunk header: {err:x?}")
         }
 
-        #[allow(deprecated)]
-        #[deprecated]
-        /// Some bits were invalid
+        /// Bits reserved by the spec were set, which is only reported if
+        /// [`WebPDecodeOptions::reject_reserved_bits`] is enabled
         ReservedBitSet {
             display("Reserved bits set")
         }
@@ -80,9 +83,10 @@ quick_error! {
             display("Invalid lossless signature: {err:x?}")
         }
 
-        /// Version Number was not zero
+        /// Version number of the lossless bitstream was not zero, or that of the lossy one was
+        /// larger than 3
         VersionNumberInvalid(err: u8) {
-            display("Invalid lossless version number: {err}")
+            display("Invalid version number: {err}")
         }
 
         /// Invalid color cache bits
@@ -167,6 +171,80 @@ quick_error! {
     }
 }
 
+/// Errors are equal if they are the same variant with the same data. I/O errors only compare
+/// their [`io::ErrorKind`].
+impl PartialEq for DecodingError {
+    fn eq(&self, other: &Self) -> bool {
+        use DecodingError::*;
+        match (self, other) {
+            (IoError(a), IoError(b)) => a.kind() == b.kind(),
+            (RiffSignatureInvalid(a), RiffSignatureInvalid(b))
+            | (WebpSignatureInvalid(a), WebpSignatureInvalid(b))
+            | (ChunkHeaderInvalid(a), ChunkHeaderInvalid(b)) => a == b,
+            (LosslessSignatureInvalid(a), LosslessSignatureInvalid(b))
+            | (VersionNumberInvalid(a), VersionNumberInvalid(b))
+            | (InvalidColorCacheBits(a), InvalidColorCacheBits(b))
+            | (ColorSpaceInvalid(a), ColorSpaceInvalid(b)) => a == b,
+            (Vp8MagicInvalid(a), Vp8MagicInvalid(b)) => a == b,
+            (LumaPredictionModeInvalid(a), LumaPredictionModeInvalid(b))
+            | (IntraPredictionModeInvalid(a), IntraPredictionModeInvalid(b))
+            | (ChromaPredictionModeInvalid(a), ChromaPredictionModeInvalid(b)) => a == b,
+            (UnsupportedFeature(a), UnsupportedFeature(b))
+            | (InvalidParameter(a), InvalidParameter(b)) => a == b,
+            (ChunkMissing, ChunkMissing)
+            | (ReservedBitSet, ReservedBitSet)
+            | (InvalidAlphaPreprocessing, InvalidAlphaPreprocessing)
+            | (InvalidCompressionMethod, InvalidCompressionMethod)
+            | (AlphaChunkSizeMismatch, AlphaChunkSizeMismatch)
+            | (ImageTooLarge, ImageTooLarge)
+            | (FrameOutsideImage, FrameOutsideImage)
+            | (HuffmanError, HuffmanError)
+            | (BitStreamError, BitStreamError)
+            | (TransformError, TransformError)
+            | (NotEnoughInitData, NotEnoughInitData)
+            | (InconsistentImageSizes, InconsistentImageSizes)
+            | (MemoryLimitExceeded, MemoryLimitExceeded)
+            | (InvalidChunkSize, InvalidChunkSize)
+            | (NoMoreFrames, NoMoreFrames) => true,
+            // Spelled out, so that a new variant has to be added here as well.
+            (
+                IoError(_)
+                | RiffSignatureInvalid(_)
+                | WebpSignatureInvalid(_)
+                | ChunkMissing
+                | ChunkHeaderInvalid(_)
+                | ReservedBitSet
+                | InvalidAlphaPreprocessing
+                | InvalidCompressionMethod
+                | AlphaChunkSizeMismatch
+                | ImageTooLarge
+                | FrameOutsideImage
+                | LosslessSignatureInvalid(_)
+                | VersionNumberInvalid(_)
+                | InvalidColorCacheBits(_)
+                | HuffmanError
+                | BitStreamError
+                | TransformError
+                | Vp8MagicInvalid(_)
+                | NotEnoughInitData
+                | ColorSpaceInvalid(_)
+                | LumaPredictionModeInvalid(_)
+                | IntraPredictionModeInvalid(_)
+                | ChromaPredictionModeInvalid(_)
+                | InconsistentImageSizes
+                | UnsupportedFeature(_)
+                | InvalidParameter(_)
+                | MemoryLimitExceeded
+                | InvalidChunkSize
+                | NoMoreFrames,
+                _,
+            ) => false,
+        }
+    }
+}
+
+impl Eq for DecodingError {}
+
 /// All possible RIFF chunks in a WebP image file
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
@@ -262,16 +340,90 @@ impl Default for AnimationState {
     }
 }
 
+/// The container format of a WebP file.
+///
+/// Simple files hold a single `VP8 ` or `VP8L` chunk, which gives their format. Extended files
+/// always start with a `VP8X` chunk, and how their image data is compressed is only given by the
+/// image chunks that follow it, the `VP8 ` or `VP8L` chunk of a still image or those inside the
+/// `ANMF` chunks of an animation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WebPFormat {
+    /// A simple lossy image, a single `VP8 ` chunk.
+    Lossy,
+    /// A simple lossless image, a single `VP8L` chunk.
+    Lossless,
+    /// An extended image with a `VP8X` header, which may have alpha, animation or metadata, and
+    /// whose image data may be lossy or lossless.
+    Extended,
+}
+
+/// Pixel format of the images returned by the decoder.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WebPColorType {
+    /// Opaque image with a red, green, and blue byte per pixel.
+    Rgb8,
+    /// Image with a red, green, blue, and alpha byte per pixel.
+    Rgba8,
+}
+
 /// Number of times that an animation loops.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum LoopCount {
     /// The animation loops forever.
     Forever,
-    /// Each frame of the animation is displayed the specified number of times.
+    /// The animation is played the specified number of times.
     Times(NonZeroU16),
 }
 
+/// The parameters of an animation needed to composite its frames, from the `VP8X` and `ANIM`
+/// chunks.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AnimationParams {
+    /// Width of the canvas in pixels.
+    pub canvas_width: u32,
+    /// Height of the canvas in pixels.
+    pub canvas_height: u32,
+    /// The background color hint in RGBA order, see
+    /// [`background_color_rgba`](WebPDecoder::background_color_rgba).
+    pub background_color: [u8; 4],
+    /// Number of times the animation should loop.
+    pub loop_count: LoopCount,
+}
+
+/// The planes of a lossy still image in the YUV 4:2:0 format it is coded in, as returned by
+/// [`read_yuv`](WebPDecoder::read_yuv).
+///
+/// The planes cover whole macroblocks, so they may extend past the image to the right and
+/// bottom. Each chroma sample covers 2x2 luma samples, and the values are limited range BT.601
+/// YCbCr.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct YuvImage {
+    /// Width of the image in pixels.
+    pub width: u32,
+    /// Height of the image in pixels.
+    pub height: u32,
+    /// The luma plane.
+    pub y: Vec<u8>,
+    /// Number of bytes between the starts of two rows of the luma plane.
+    pub y_stride: usize,
+    /// The blue difference chroma plane, at half the resolution of the luma plane.
+    pub u: Vec<u8>,
+    /// The red difference chroma plane, at half the resolution of the luma plane.
+    pub v: Vec<u8>,
+    /// Number of bytes between the starts of two rows of either chroma plane.
+    pub uv_stride: usize,
+    /// The unpremultiplied alpha plane of `width` x `height` bytes, if the image has alpha.
+    pub alpha: Option<Vec<u8>>,
+}
+
 /// WebP decoder configuration options
+///
+/// The options are passed to [`WebPDecoder::new_with_options`]. Those that only affect how pixels
+/// are decoded can still be changed afterwards through the `set_*` methods of the decoder. The
+/// options that are checked while the headers are parsed, such as
+/// [`max_dimensions`](Self::max_dimensions) and [`lenient_riff_size`](Self::lenient_riff_size),
+/// have no setters, because the decoder has already applied them once it exists.
 #[derive(Clone)]
 #[non_exhaustive]
 pub struct WebPDecodeOptions {
@@ -279,12 +431,91 @@ pub struct WebPDecodeOptions {
     ///
     /// Defaults to `Bilinear`.
     pub lossy_upsampling: UpsamplingMethod,
+
+    /// Whether the loop filter is applied to lossy images
+    ///
+    /// Disabling it speeds up decoding at the cost of visible blocking artifacts, which may be
+    /// acceptable for previews. Defaults to `true`.
+    pub loop_filter: bool,
+
+    /// Whether the prediction modes and quantizers of each macroblock of lossy images are
+    /// recorded, along with how much of each partition was consumed, for tools that analyze the
+    /// choices of an encoder
+    ///
+    /// See [`WebPDecoder::prediction_modes`], [`WebPDecoder::quantizers`] and
+    /// [`WebPDecoder::partition_usage`]. Defaults to `false`.
+    pub macroblock_info: bool,
+
+    /// The index of the macroblock of lossy images, in raster order, whose dequantized DCT
+    /// coefficients are recorded
+    ///
+    /// See [`WebPDecoder::macroblock_coefficients`]. Only a single macroblock is recorded, so
+    /// this costs nothing for the others. Defaults to `None`.
+    pub macroblock_coefficients: Option<usize>,
+
+    /// Whether the color channels of images with alpha are multiplied by the alpha channel, as
+    /// GPU blending usually expects
+    ///
+    /// Applies to all methods returning RGBA pixels, but not to
+    /// [`WebPDecoder::read_image_gray_alpha`] and [`WebPDecoder::read_yuv`]. Defaults to `false`.
+    pub premultiply_alpha: bool,
+
+    /// Whether lossy images whose data was cut short are decoded as far as possible instead of
+    /// returning an error
+    ///
+    /// The macroblocks from the first row with missing data onwards are filled with neutral gray
+    /// and [`WebPDecoder::is_incomplete`] reports that this happened. A simple lossy image whose
+    /// RIFF chunk extends past the end of the stream is only accepted if this is passed to
+    /// [`WebPDecoder::new_with_options`]. Defaults to `false`.
+    pub error_concealment: bool,
+
+    /// The largest width and height of images that are accepted, checked as soon as the header
+    /// of the image is parsed, before anything is allocated for it
+    ///
+    /// [`WebPDecoder::new_with_options`] fails with `ImageTooLarge` for larger images, which
+    /// rejects decompression bombs early. Simple images can't exceed 16383x16383, but the canvas
+    /// of extended images can be up to 16777216 pixels wide and high. Defaults to no limit beyond
+    /// that of the format.
+    pub max_dimensions: (u32, u32),
+
+    /// The strength from 0 to 100 of the noise that is added to the chroma of flat macroblocks
+    /// in heavily quantized lossy images, which breaks up the banding of smooth gradients
+    ///
+    /// The noise is the same as that of `dwebp -dither`. Defaults to 0, which disables dithering.
+    pub dithering_strength: u8,
+
+    /// Whether the size in the RIFF header is ignored if it is implausible, which some broken
+    /// encoders leave as 0 or get wrong
+    ///
+    /// The RIFF chunk is then taken to end with the stream if its size is too small to hold an
+    /// image, if it extends past the end of the stream, or if the image data of a simple file
+    /// extends past it. Image data that extends past the end of the stream is still rejected as
+    /// truncated. Only applies to [`WebPDecoder::new_with_options`]. Defaults to `false`.
+    pub lenient_riff_size: bool,
+
+    /// Whether files with reserved bits set are rejected with `ReservedBitSet`, for testing the
+    /// conformance of encoders
+    ///
+    /// The reserved bits of the flags and the 24 reserved bits following them in the `VP8X`
+    /// header are checked by [`WebPDecoder::new_with_options`], and those of the flags in each
+    /// `ANMF` header by [`WebPDecoder::read_frame`]. The spec requires them to be zero, but
+    /// decoders, libwebp included, ignore them by default. Defaults to `false`.
+    pub reject_reserved_bits: bool,
 }
 
 impl Default for WebPDecodeOptions {
     fn default() -> Self {
         Self {
             lossy_upsampling: UpsamplingMethod::Bilinear,
+            loop_filter: true,
+            macroblock_info: false,
+            macroblock_coefficients: None,
+            premultiply_alpha: false,
+            error_concealment: false,
+            max_dimensions: (u32::MAX, u32::MAX),
+            dithering_strength: 0,
+            lenient_riff_size: false,
+            reject_reserved_bits: false,
         }
     }
 }
@@ -308,6 +539,27 @@ pub enum UpsamplingMethod {
     Simple,
 }
 
+/// Factor by which [`WebPDecoder::read_image_scaled`] downscales an image
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ScaleFactor {
+    /// Half of the width and height
+    Half,
+    /// A quarter of the width and height
+    Quarter,
+    /// An eighth of the width and height
+    Eighth,
+}
+
+impl ScaleFactor {
+    const fn divisor(self) -> u32 {
+        match self {
+            Self::Half => 2,
+            Self::Quarter => 4,
+            Self::Eighth => 8,
+        }
+    }
+}
+
 /// WebP image format decoder.
 pub struct WebPDecoder<R> {
     r: R,
@@ -328,6 +580,16 @@ pub struct WebPDecoder<R> {
     chunks: HashMap<WebPRiffChunk, Range<u64>>,
 
     webp_decode_options: WebPDecodeOptions,
+
+    /// Planes of the last decoded lossy frame, kept around to reuse their allocations.
+    vp8_frame: Frame,
+    vp8_frame_header: Option<FrameHeader>,
+
+    /// EXIF orientation applied to the output, from 1 to 8, where 1 leaves the image as it is.
+    orientation: u8,
+
+    /// Upscaling of the width and height requested by the header of a simple lossy image.
+    display_scale: (u8, u8),
 }
 
 impl<R: BufRead + Seek> WebPDecoder<R> {
@@ -357,24 +619,78 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
             loop_count: LoopCount::Times(NonZeroU16::new(1).unwrap()),
             loop_duration: 0,
             webp_decode_options,
+            vp8_frame: Frame::default(),
+            vp8_frame_header: None,
+            orientation: 1,
+            display_scale: (0, 0),
         };
         decoder.read_data()?;
         Ok(decoder)
     }
 
     fn read_data(&mut self) -> Result<(), DecodingError> {
+        let riff_start = self.r.stream_position()?;
         let (WebPRiffChunk::RIFF, riff_size, _) = read_chunk_header(&mut self.r)? else {
             return Err(DecodingError::ChunkHeaderInvalid(*b"RIFF"));
         };
 
+        let stream_end = self.r.seek(io::SeekFrom::End(0))?;
+        let lenient_riff_size = self.webp_decode_options.lenient_riff_size;
+        if riff_size < 12 && !lenient_riff_size {
+            return Err(DecodingError::InvalidChunkSize);
+        }
+        self.r.seek(io::SeekFrom::Start(riff_start + 8))?;
+
         match &read_fourcc(&mut self.r)? {
             WebPRiffChunk::WEBP => {}
             fourcc => return Err(DecodingError::WebpSignatureInvalid(fourcc.to_fourcc())),
         }
 
-        let (chunk, chunk_size, chunk_size_rounded) = read_chunk_header(&mut self.r)?;
+        // Skip any chunks preceding the image data, which some encoders write even though the spec
+        // requires the image chunk to come first.
+        let (chunk, chunk_size, chunk_size_rounded) = loop {
+            let (chunk, chunk_size, chunk_size_rounded) = match read_chunk_header(&mut self.r) {
+                Err(DecodingError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Err(DecodingError::ChunkMissing)
+                }
+                result => result?,
+            };
+            if let WebPRiffChunk::VP8 | WebPRiffChunk::VP8L | WebPRiffChunk::VP8X = chunk {
+                break (chunk, chunk_size, chunk_size_rounded);
+            }
+
+            let next = self.r.stream_position()? + chunk_size_rounded;
+            let chunks_end = if lenient_riff_size {
+                stream_end
+            } else {
+                riff_start + 8 + riff_size
+            };
+            if next + 8 > chunks_end {
+                return Err(DecodingError::ChunkMissing);
+            }
+            self.r.seek(io::SeekFrom::Start(next))?;
+        };
         let start = self.r.stream_position()?;
 
+        // The RIFF chunk must fit in the stream, but like libwebp we ignore trailing data after it.
+        let mut riff_end = riff_start + 8 + riff_size;
+        let image_past_riff_end =
+            |riff_end| chunk != WebPRiffChunk::VP8X && start + chunk_size > riff_end;
+        if lenient_riff_size
+            && (riff_size < 12 || riff_end > stream_end || image_past_riff_end(riff_end))
+        {
+            riff_end = stream_end;
+        }
+        let riff_size = riff_end - riff_start - 8;
+
+        // With error concealment, a lossy image whose data was cut short is decoded as far as
+        // possible.
+        if (riff_end > stream_end || image_past_riff_end(riff_end))
+            && !(chunk == WebPRiffChunk::VP8 && self.webp_decode_options.error_concealment)
+        {
+            return Err(DecodingError::InvalidChunkSize);
+        }
+
         match chunk {
             WebPRiffChunk::VP8 => {
                 let tag = self.r.read_u24::<LittleEndian>()?;
@@ -397,6 +713,8 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
 
                 self.width = u32::from(w & 0x3FFF);
                 self.height = u32::from(h & 0x3FFF);
+                self.display_scale = ((w >> 14) as u8, (h >> 14) as u8);
+                self.check_max_dimensions()?;
                 if self.width == 0 || self.height == 0 {
                     return Err(DecodingError::InconsistentImageSizes);
                 }
@@ -420,6 +738,7 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
 
                 self.width = (1 + header) & 0x3FFF;
                 self.height = (1 + (header >> 14)) & 0x3FFF;
+                self.check_max_dimensions()?;
                 self.chunks
                     .insert(WebPRiffChunk::VP8L, start..start + chunk_size);
                 self.kind = ImageKind::Lossless;
@@ -427,8 +746,12 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
             }
             WebPRiffChunk::VP8X => {
                 let mut info = extended::read_extended_header(&mut self.r)?;
+                if info.reserved_bits_set && self.webp_decode_options.reject_reserved_bits {
+                    return Err(DecodingError::ReservedBitSet);
+                }
                 self.width = info.canvas_width;
                 self.height = info.canvas_height;
+                self.check_max_dimensions()?;
 
                 let mut position = start + chunk_size_rounded;
                 let max_position = position + riff_size.saturating_sub(12);
@@ -542,12 +865,20 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
                 self.has_alpha = info.alpha;
                 self.kind = ImageKind::Extended(info);
             }
-            _ => return Err(DecodingError::ChunkHeaderInvalid(chunk.to_fourcc())),
+            _ => unreachable!(),
         };
 
         Ok(())
     }
 
+    fn check_max_dimensions(&self) -> Result<(), DecodingError> {
+        let (max_width, max_height) = self.webp_decode_options.max_dimensions;
+        if self.width > max_width || self.height > max_height {
+            return Err(DecodingError::ImageTooLarge);
+        }
+        Ok(())
+    }
+
     /// Sets the maximum amount of memory that the decoder is allowed to allocate at once.
     ///
     /// TODO: Some allocations currently ignore this limit.
@@ -556,6 +887,9 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
     }
 
     /// Get the background color specified in the image file if the image is extended and animated webp.
+    ///
+    /// The color is in the BGRA order it is stored in, see
+    /// [`background_color_rgba`](Self::background_color_rgba) for the RGBA one.
     pub fn background_color_hint(&self) -> Option<[u8; 4]> {
         if let ImageKind::Extended(info) = &self.kind {
             Some(info.background_color_hint)
@@ -564,6 +898,13 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
         }
     }
 
+    /// Get the background color specified in the image file in RGBA order, like the pixels
+    /// returned by the decoder, if the image is extended and animated webp.
+    pub fn background_color_rgba(&self) -> Option<[u8; 4]> {
+        self.background_color_hint()
+            .map(|[b, g, r, a]| [r, g, b, a])
+    }
+
     /// Sets the background color if the image is an extended and animated webp.
     pub fn set_background_color(&mut self, color: [u8; 4]) -> Result<(), DecodingError> {
         if let ImageKind::Extended(info) = &mut self.kind {
@@ -576,17 +917,77 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
         }
     }
 
-    /// Returns the (width, height) of the image in pixels.
+    /// Returns the (width, height) of the image in pixels, which is the size of the output of
+    /// [`read_image`](Self::read_image) and [`read_frame`](Self::read_frame).
+    ///
+    /// These are swapped for rotated images if [`set_auto_orient`](Self::set_auto_orient) is
+    /// enabled. See [`coded_dimensions`](Self::coded_dimensions) for the stored size and
+    /// [`display_dimensions`](Self::display_dimensions) for the size after upscaling.
     pub fn dimensions(&self) -> (u32, u32) {
+        if self.orientation > 4 {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+
+    /// Returns the (width, height) of the image as it is stored, before any rotation or
+    /// upscaling. For extended images, this is the canvas size.
+    ///
+    /// This is the size of the output of
+    /// [`read_image_streaming`](Self::read_image_streaming), and the coordinates of
+    /// [`read_region`](Self::read_region), [`read_tile`](Self::read_tile) and
+    /// [`read_rows`](Self::read_rows) refer to it.
+    pub fn coded_dimensions(&self) -> (u32, u32) {
         (self.width, self.height)
     }
 
+    /// Returns the (width, height) at which a simple lossy image asks to be displayed, which
+    /// differs from [`dimensions`](Self::dimensions) if its header requests upscaling by 5/4, 5/3
+    /// or 2 in either direction.
+    ///
+    /// Like libwebp, the decoder always outputs the coded size and leaves the upscaling, whose
+    /// filter the VP8 specification doesn't define, to the application. Extended images are
+    /// displayed at their canvas size.
+    pub fn display_dimensions(&self) -> (u32, u32) {
+        fn scale(size: u32, scale: u8) -> u32 {
+            let (num, den) = match scale {
+                0 => (1, 1),
+                1 => (5, 4),
+                2 => (5, 3),
+                _ => (2, 1),
+            };
+            (size * num + den / 2) / den
+        }
+
+        let (width, height) = (
+            scale(self.width, self.display_scale.0),
+            scale(self.height, self.display_scale.1),
+        );
+        if self.orientation > 4 {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
     /// Returns whether the image has an alpha channel. If so, the pixel format is Rgba8 and
     /// otherwise Rgb8.
     pub fn has_alpha(&self) -> bool {
         self.has_alpha
     }
 
+    /// Returns the pixel format of the output of [`read_image`](Self::read_image) and
+    /// [`read_frame`](Self::read_frame), which is either `Rgba8` or `Rgb8` depending on
+    /// [`has_alpha`](Self::has_alpha).
+    pub fn color_type(&self) -> WebPColorType {
+        if self.has_alpha() {
+            WebPColorType::Rgba8
+        } else {
+            WebPColorType::Rgb8
+        }
+    }
+
     /// Returns true if the image is animated.
     pub fn is_animated(&self) -> bool {
         match &self.kind {
@@ -595,11 +996,107 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
         }
     }
 
+    /// Returns the container format of the file, which is known without decoding any pixels. Use
+    /// [`is_lossy`](Self::is_lossy) to tell how the image data of extended files is compressed.
+    pub fn format(&self) -> WebPFormat {
+        match &self.kind {
+            ImageKind::Lossy => WebPFormat::Lossy,
+            ImageKind::Lossless => WebPFormat::Lossless,
+            ImageKind::Extended(_) => WebPFormat::Extended,
+        }
+    }
+
     /// Returns whether the image is lossy. For animated images, this is true if any frame is lossy.
     pub fn is_lossy(&mut self) -> bool {
         self.is_lossy
     }
 
+    /// Returns the header of the most recently decoded lossy frame, or `None` if no lossy frame
+    /// has been decoded yet.
+    pub fn frame_header(&self) -> Option<FrameHeader> {
+        self.vp8_frame_header
+    }
+
+    /// Returns the number of macroblock columns and rows of the most recently decoded lossy frame,
+    /// or `(0, 0)` if no lossy frame has been decoded yet.
+    ///
+    /// Each macroblock covers 16x16 pixels, and those at the right and bottom edges may extend
+    /// past the frame.
+    pub fn macroblock_dimensions(&self) -> (usize, usize) {
+        (
+            usize::from(self.vp8_frame.width).div_ceil(16),
+            usize::from(self.vp8_frame.height).div_ceil(16),
+        )
+    }
+
+    /// Returns the prediction modes of the macroblocks of the most recently decoded lossy frame
+    /// in raster order, with as many macroblocks per row as
+    /// [`macroblock_dimensions`](Self::macroblock_dimensions) reports.
+    ///
+    /// This is empty unless recording was enabled with
+    /// [`set_macroblock_info`](Self::set_macroblock_info) before decoding.
+    pub fn prediction_modes(&self) -> &[MacroblockInfo] {
+        &self.vp8_frame.macroblock_info
+    }
+
+    /// Returns the quantizer step sizes of the macroblocks of the most recently decoded lossy
+    /// frame, in the same order as [`prediction_modes`](Self::prediction_modes).
+    ///
+    /// This is empty unless recording was enabled with
+    /// [`set_macroblock_info`](Self::set_macroblock_info) before decoding.
+    pub fn quantizers(&self) -> &[MacroblockQuant] {
+        &self.vp8_frame.macroblock_quant
+    }
+
+    /// Returns the dequantized DCT coefficients of the macroblock selected with
+    /// [`set_macroblock_coefficients`](Self::set_macroblock_coefficients) in the most recently
+    /// decoded lossy frame.
+    ///
+    /// This is None if no macroblock was selected before decoding, or if the frame has no
+    /// macroblock with that index.
+    pub fn macroblock_coefficients(&self) -> Option<&MacroblockCoefficients> {
+        self.vp8_frame.macroblock_coefficients.as_ref()
+    }
+
+    /// Returns whether the data of the most recently decoded lossy frame was cut short and its
+    /// bottom part was concealed, which only happens if
+    /// [`set_error_concealment`](Self::set_error_concealment) is enabled.
+    pub fn is_incomplete(&self) -> bool {
+        self.vp8_frame.incomplete
+    }
+
+    /// Returns whether the loop filter was applied to the most recently decoded lossy frame.
+    ///
+    /// This is false if the filter was disabled with [`set_loop_filter`](Self::set_loop_filter),
+    /// and also if the filter level of the frame header, or that of every macroblock after the
+    /// segment and mode adjustments, is zero.
+    pub fn loop_filter_applied(&self) -> bool {
+        self.vp8_frame.loop_filtered
+    }
+
+    /// Returns the number of DCT token partitions of the most recently decoded lossy frame, or
+    /// zero if no lossy frame has been decoded yet.
+    pub fn num_partitions(&self) -> u8 {
+        self.vp8_frame.partition_sizes.len() as u8
+    }
+
+    /// Returns the sizes in bytes of the DCT token partitions of the most recently decoded lossy
+    /// frame, as given by the partition size table of the frame. The size of the last partition
+    /// is the rest of the frame data.
+    pub fn partition_sizes(&self) -> &[usize] {
+        &self.vp8_frame.partition_sizes
+    }
+
+    /// Returns how many bytes of each partition of the most recently decoded lossy frame were
+    /// consumed, together with the size of the partition, starting with the first partition that
+    /// holds the modes and followed by the DCT token partitions.
+    ///
+    /// A valid stream consumes about all of each partition. This is empty unless recording was
+    /// enabled with [`set_macroblock_info`](Self::set_macroblock_info) before decoding.
+    pub fn partition_usage(&self) -> &[(usize, usize)] {
+        &self.vp8_frame.partition_usage
+    }
+
     /// Returns the number of frames of a single loop of the animation, or zero if the image is not
     /// animated.
     pub fn num_frames(&self) -> u32 {
@@ -607,10 +1104,32 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
     }
 
     /// Returns the number of times the animation should loop.
+    ///
+    /// A loop count of zero in the `ANIM` chunk is reported as [`LoopCount::Forever`].
     pub fn loop_count(&self) -> LoopCount {
         self.loop_count
     }
 
+    /// Returns the canvas size, background color and loop count of an animated image, or None if
+    /// the image is not animated.
+    ///
+    /// These are known once the decoder is created, so they can be queried before any frame is
+    /// decoded, for example to composite the frames returned by a custom compositor.
+    pub fn animation_params(&self) -> Option<AnimationParams> {
+        match &self.kind {
+            ImageKind::Extended(info) if info.animation => {
+                let [b, g, r, a] = info.background_color_hint;
+                Some(AnimationParams {
+                    canvas_width: info.canvas_width,
+                    canvas_height: info.canvas_height,
+                    background_color: [r, g, b, a],
+                    loop_count: self.loop_count,
+                })
+            }
+            _ => None,
+        }
+    }
+
     /// Returns the total duration of one loop through the animation in milliseconds, or zero if the
     /// image is not animated.
     ///
@@ -663,10 +1182,137 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
             .checked_mul(bytes_per_pixel)
     }
 
+    /// Returns the number of bytes required to store the image or a single frame as luma and alpha
+    /// pairs, or None if that would take more than `usize::MAX` bytes.
+    pub fn output_buffer_size_gray_alpha(&self) -> Option<usize> {
+        (self.width as usize)
+            .checked_mul(self.height as usize)?
+            .checked_mul(2)
+    }
+
+    /// Returns the image as pairs of luma and alpha bytes. For animated images, this is the first
+    /// frame. Images without alpha are opaque.
+    ///
+    /// The luma is the Y' of limited range BT.601 YCbCr. Lossy still images store it directly,
+    /// so their chroma planes aren't converted at all. For lossless and animated images it is
+    /// computed from the RGB pixels with the coefficients of the encoder.
+    ///
+    /// The alpha is never premultiplied, but the image is oriented like
+    /// [`read_image`](Self::read_image) does.
+    ///
+    /// Fails with `ImageTooLarge` if `buf` has length different than
+    /// `output_buffer_size_gray_alpha()`
+    pub fn read_image_gray_alpha(&mut self, buf: &mut [u8]) -> Result<(), DecodingError> {
+        if Some(buf.len()) != self.output_buffer_size_gray_alpha() {
+            return Err(DecodingError::ImageTooLarge);
+        }
+
+        if self.is_animated() || self.chunks.contains_key(&WebPRiffChunk::VP8L) {
+            let mut image = vec![
+                0;
+                self.output_buffer_size()
+                    .ok_or(DecodingError::ImageTooLarge)?
+            ];
+            self.decode_image(&mut image)?;
+
+            let bytes_per_pixel = if self.has_alpha() { 4 } else { 3 };
+            for (gray_alpha, pixel) in buf
+                .chunks_exact_mut(2)
+                .zip(image.chunks_exact(bytes_per_pixel))
+            {
+                gray_alpha[0] = yuv::rgb_to_y(pixel);
+                gray_alpha[1] = pixel.get(3).copied().unwrap_or(0xff);
+            }
+        } else {
+            let width = self.width as usize;
+            self.decode_lossy_rows(
+                self.height as usize,
+                &mut |frame, range, mut alpha_chunk| {
+                    if let Some(alpha_chunk) = &mut alpha_chunk {
+                        alpha_chunk.unfilter_rows(range.clone(), width);
+                    }
+                    for y in range {
+                        let row = &mut buf[y * width * 2..][..width * 2];
+                        for (gray_alpha, &luma) in row.chunks_exact_mut(2).zip(frame.luma_row(y)) {
+                            gray_alpha[0] = luma;
+                            gray_alpha[1] = 0xff;
+                        }
+                        if let Some(alpha_chunk) = &alpha_chunk {
+                            for (gray_alpha, &alpha) in row
+                                .chunks_exact_mut(2)
+                                .zip(&alpha_chunk.data[y * width..][..width])
+                            {
+                                gray_alpha[1] = alpha;
+                            }
+                        }
+                    }
+                    ControlFlow::Continue(())
+                },
+            )?;
+        }
+
+        if self.orientation != 1 {
+            extended::apply_orientation(buf, self.width, self.height, 2, self.orientation);
+        }
+        Ok(())
+    }
+
+    /// Returns the planes of a lossy still image without converting them to RGB, for example to
+    /// hand them to a video encoder.
+    ///
+    /// Unlike [`read_image`](Self::read_image), the image is not oriented.
+    ///
+    /// Fails with `InvalidParameter` if the image is lossless or animated.
+    pub fn read_yuv(&mut self) -> Result<YuvImage, DecodingError> {
+        if self.is_animated() || !self.chunks.contains_key(&WebPRiffChunk::VP8) {
+            return Err(DecodingError::InvalidParameter(
+                "only lossy still images can be decoded to YUV".to_owned(),
+            ));
+        }
+
+        let width = self.width as usize;
+        let mut alpha = self
+            .has_alpha()
+            .then(|| vec![0xff; width * self.height as usize]);
+        self.decode_lossy_rows(self.height as usize, &mut |_, range, alpha_chunk| {
+            if let (Some(alpha), Some(alpha_chunk)) = (&mut alpha, alpha_chunk) {
+                alpha_chunk.unfilter_rows(range.clone(), width);
+                let range = range.start * width..range.end * width;
+                alpha[range.clone()].copy_from_slice(&alpha_chunk.data[range]);
+            }
+            ControlFlow::Continue(())
+        })?;
+
+        let y_stride = width.div_ceil(16) * 16;
+        Ok(YuvImage {
+            width: self.width,
+            height: self.height,
+            y: std::mem::take(&mut self.vp8_frame.ybuf),
+            y_stride,
+            u: std::mem::take(&mut self.vp8_frame.ubuf),
+            v: std::mem::take(&mut self.vp8_frame.vbuf),
+            uv_stride: y_stride / 2,
+            alpha,
+        })
+    }
+
     /// Returns the raw bytes of the image. For animated images, this is the first frame.
     ///
+    /// Lossy images are converted from YUV to RGB with the limited range BT.601 coefficients the
+    /// format is defined with, matching libwebp.
+    ///
+    /// If [`set_auto_orient`](Self::set_auto_orient) is enabled, the image is rotated and flipped
+    /// according to its EXIF orientation.
+    ///
     /// Fails with `ImageTooLarge` if `buf` has length different than `output_buffer_size()`
     pub fn read_image(&mut self, buf: &mut [u8]) -> Result<(), DecodingError> {
+        self.decode_image(buf)?;
+        self.finish_output(buf, self.width, self.height);
+        Ok(())
+    }
+
+    /// Decodes the image like [`read_image`](Self::read_image) but in its stored orientation.
+    fn decode_image(&mut self, buf: &mut [u8]) -> Result<(), DecodingError> {
         if Some(buf.len()) != self.output_buffer_size() {
             return Err(DecodingError::ImageTooLarge);
         }
@@ -675,7 +1321,7 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
             let saved = std::mem::take(&mut self.animation);
             self.animation.next_frame_start =
                 self.chunks.get(&WebPRiffChunk::ANMF).unwrap().start - 8;
-            let result = self.read_frame(buf);
+            let result = self.decode_frame(buf);
             self.animation = saved;
             result?;
         } else if let Some(range) = self.chunks.get(&WebPRiffChunk::VP8L) {
@@ -691,52 +1337,562 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
                 }
             }
         } else {
-            let range = self
-                .chunks
-                .get(&WebPRiffChunk::VP8)
-                .ok_or(DecodingError::ChunkMissing)?;
-            let reader = range_reader(&mut self.r, range.start..range.end)?;
-            let frame = Vp8Decoder::decode_frame(reader)?;
-            if u32::from(frame.width) != self.width || u32::from(frame.height) != self.height {
-                return Err(DecodingError::InconsistentImageSizes);
+            self.read_lossy_image(buf, self.height as usize, false, &mut |_| {})?;
+        }
+
+        Ok(())
+    }
+
+    /// Like [`read_image`](Self::read_image), but calls `on_row` with the index of each output row
+    /// as soon as it is final, in top to bottom order, which allows showing the image while it is
+    /// still being decoded.
+    ///
+    /// For lossy images, the loop filter of a macroblock row modifies the bottom pixels of the row
+    /// above it, so rows become final one macroblock row (16 pixels) behind reconstruction. Each
+    /// row is premultiplied before it is reported if
+    /// [`set_premultiply_alpha`](Self::set_premultiply_alpha) is enabled. Lossless and animated
+    /// images, and images rotated by [`set_auto_orient`](Self::set_auto_orient), are decoded as a
+    /// whole, so `on_row` is only called once they are done.
+    ///
+    /// Fails with `ImageTooLarge` if `buf` has length different than `output_buffer_size()`
+    pub fn read_image_streaming(
+        &mut self,
+        buf: &mut [u8],
+        mut on_row: impl FnMut(usize),
+    ) -> Result<(), DecodingError> {
+        if self.is_animated()
+            || self.chunks.contains_key(&WebPRiffChunk::VP8L)
+            || self.orientation != 1
+        {
+            self.read_image(buf)?;
+            (0..self.dimensions().1 as usize).for_each(on_row);
+            return Ok(());
+        }
+
+        if Some(buf.len()) != self.output_buffer_size() {
+            return Err(DecodingError::ImageTooLarge);
+        }
+        let premultiply = self.webp_decode_options.premultiply_alpha && self.has_alpha();
+        self.read_lossy_image(buf, self.height as usize, premultiply, &mut on_row)
+    }
+
+    /// Like [`read_image`](Self::read_image), but calls `progress` with the fraction of the image
+    /// that is decoded, from 0.0 to 1.0, whenever a macroblock row (16 pixel rows) is final. The
+    /// last call reports 1.0 once the output is complete.
+    ///
+    /// Lossless and animated images, and images rotated by
+    /// [`set_auto_orient`](Self::set_auto_orient), are decoded as a whole like in
+    /// [`read_image_streaming`](Self::read_image_streaming), so all calls of `progress` happen
+    /// once they are done.
+    ///
+    /// Fails with `ImageTooLarge` if `buf` has length different than `output_buffer_size()`
+    pub fn read_image_with_progress(
+        &mut self,
+        buf: &mut [u8],
+        mut progress: impl FnMut(f32),
+    ) -> Result<(), DecodingError> {
+        // Rows are reported in the orientation of the output
+        let height = self.dimensions().1 as usize;
+        self.read_image_streaming(buf, |row| {
+            if (row + 1) % 16 == 0 && row + 1 < height {
+                progress((row + 1) as f32 / height as f32);
             }
+        })?;
+        progress(1.0);
+        Ok(())
+    }
+
+    /// Returns the raw bytes of the first `rows` rows of the image. For animated images, these are
+    /// the top rows of the first frame.
+    ///
+    /// The rows are read as a region with [`read_region`](Self::read_region), so they are the top
+    /// rows of the displayed image and the output options are applied like in
+    /// [`read_image`](Self::read_image). For lossy still images, decoding stops once the requested
+    /// rows are complete, which saves most of the work when only a strip at the top of a tall
+    /// image is needed. The decoder can be used to read the image again afterwards. Lossless and
+    /// animated images are fully decoded.
+    ///
+    /// Fails with `FrameOutsideImage` if `rows` is larger than the height of `dimensions()`, and
+    /// with `ImageTooLarge` if `buf` is not exactly large enough to hold the rows.
+    pub fn read_rows(&mut self, rows: usize, buf: &mut [u8]) -> Result<(), DecodingError> {
+        let rows = u32::try_from(rows).map_err(|_| DecodingError::FrameOutsideImage)?;
+        let (width, _) = self.dimensions();
+        self.read_region(0, 0, width, rows, buf)
+    }
+
+    /// Writes the bytes that [`read_image`](Self::read_image) returns to `writer`, one row at a
+    /// time, which suits piping the pixels to another program.
+    ///
+    /// The rows of lossy still images are converted and written as soon as they are final, so
+    /// the image is never held in RGB form as a whole. Lossless and animated images, and images
+    /// rotated by [`set_auto_orient`](Self::set_auto_orient), are decoded into a buffer first.
+    /// Errors of the writer are returned as `IoError` and stop decoding.
+    pub fn read_image_to_writer<W: io::Write>(
+        &mut self,
+        writer: &mut W,
+    ) -> Result<(), DecodingError> {
+        if self.is_animated()
+            || self.chunks.contains_key(&WebPRiffChunk::VP8L)
+            || self.orientation != 1
+        {
+            let mut image = vec![
+                0;
+                self.output_buffer_size()
+                    .ok_or(DecodingError::ImageTooLarge)?
+            ];
+            self.read_image(&mut image)?;
+            writer.write_all(&image)?;
+            return Ok(());
+        }
 
-            if self.has_alpha() {
-                frame.fill_rgba(buf, self.webp_decode_options.lossy_upsampling);
-
-                let range = self
-                    .chunks
-                    .get(&WebPRiffChunk::ALPH)
-                    .ok_or(DecodingError::ChunkMissing)?
-                    .clone();
-                let alpha_chunk = read_alpha_chunk(
-                    &mut range_reader(&mut self.r, range)?,
-                    self.width as u16,
-                    self.height as u16,
-                )?;
-
-                for y in 0..frame.height {
-                    for x in 0..frame.width {
-                        let predictor: u8 = get_alpha_predictor(
-                            x.into(),
-                            y.into(),
-                            frame.width.into(),
-                            alpha_chunk.filtering_method,
-                            buf,
-                        );
-
-                        let alpha_index =
-                            usize::from(y) * usize::from(frame.width) + usize::from(x);
-                        let buffer_index = alpha_index * 4 + 3;
-
-                        buf[buffer_index] = predictor.wrapping_add(alpha_chunk.data[alpha_index]);
+        let bytes_per_pixel = if self.has_alpha() { 4 } else { 3 };
+        let premultiply = self.webp_decode_options.premultiply_alpha && self.has_alpha();
+        let upsampling = self.webp_decode_options.lossy_upsampling;
+        let mut row = vec![0; self.width as usize * bytes_per_pixel];
+        let mut write_error = None;
+        self.decode_lossy_rows(
+            self.height as usize,
+            &mut |frame, range, mut alpha_chunk| {
+                if let Some(alpha_chunk) = &mut alpha_chunk {
+                    alpha_chunk.unfilter_rows(range.clone(), usize::from(frame.width));
+                }
+                for y in range {
+                    if let Some(alpha_chunk) = &alpha_chunk {
+                        fill_rgba_row(frame, &mut row, y, upsampling, alpha_chunk);
+                    } else {
+                        frame.fill_rgb_row::<3>(&mut row, y, upsampling);
+                    }
+                    if premultiply {
+                        alpha_blending::premultiply_alpha(&mut row);
                     }
+                    if let Err(e) = writer.write_all(&row) {
+                        write_error = Some(e);
+                        return ControlFlow::Break(());
+                    }
+                }
+                ControlFlow::Continue(())
+            },
+        )?;
+
+        match write_error {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads the `ALPH` chunk of a lossy still image, if it has alpha.
+    fn read_lossy_alpha(&mut self) -> Result<Option<AlphaChunk>, DecodingError> {
+        if !self.has_alpha() {
+            return Ok(None);
+        }
+
+        let range = self
+            .chunks
+            .get(&WebPRiffChunk::ALPH)
+            .ok_or(DecodingError::ChunkMissing)?
+            .clone();
+        Ok(Some(read_alpha_chunk(
+            &mut range_reader(&mut self.r, range)?,
+            self.width as u16,
+            self.height as u16,
+        )?))
+    }
+
+    /// Decodes the `VP8 ` chunk and the optional `ALPH` chunk of a still image into the first
+    /// `rows` rows of the image, converting, optionally premultiplying, and reporting the rows as
+    /// they are completed.
+    fn read_lossy_image(
+        &mut self,
+        buf: &mut [u8],
+        rows: usize,
+        premultiply: bool,
+        on_row: &mut dyn FnMut(usize),
+    ) -> Result<(), DecodingError> {
+        let upsampling = self.webp_decode_options.lossy_upsampling;
+        let buf_len = buf.len();
+        let mut bytes_written = 0;
+        self.decode_lossy_rows(rows, &mut |frame, range, alpha_chunk| {
+            let bytes_per_pixel = if let Some(alpha_chunk) = alpha_chunk {
+                fill_rgba_rows(frame, buf, range.clone(), upsampling, alpha_chunk);
+                if premultiply {
+                    let width = usize::from(frame.width);
+                    alpha_blending::premultiply_alpha(
+                        &mut buf[range.start * width * 4..range.end * width * 4],
+                    );
                 }
+                4
             } else {
-                frame.fill_rgb(buf, self.webp_decode_options.lossy_upsampling);
+                frame.fill_rgb_rows::<3>(buf, range.clone(), upsampling);
+                3
+            };
+            bytes_written += range.len() * usize::from(frame.width) * bytes_per_pixel;
+            range.for_each(&mut *on_row);
+            ControlFlow::Continue(())
+        })?;
+        // The callers size `buf` to hold exactly the requested rows, so every row of it must have
+        // been converted.
+        debug_assert_eq!(bytes_written, buf_len);
+        Ok(())
+    }
+
+    /// Decodes the `VP8 ` chunk and the optional `ALPH` chunk of a still image until the first
+    /// `rows` rows are complete, passing each range of newly completed rows to `on_rows` along
+    /// with the alpha chunk, whose rows still have to be unfiltered. Decoding stops early if
+    /// `on_rows` returns `ControlFlow::Break`.
+    fn decode_lossy_rows(
+        &mut self,
+        rows: usize,
+        on_rows: &mut OnLossyRows<'_>,
+    ) -> Result<(), DecodingError> {
+        let mut alpha_chunk = self.read_lossy_alpha()?;
+
+        let range = self
+            .chunks
+            .get(&WebPRiffChunk::VP8)
+            .ok_or(DecodingError::ChunkMissing)?;
+        let reader = range_reader(&mut self.r, range.start..range.end)?;
+        let (width, height) = (self.width, self.height);
+        let mut next_row = 0;
+        let mut frame = std::mem::take(&mut self.vp8_frame);
+        Vp8Decoder::decode_frame_into(
+            reader,
+            &mut frame,
+            &self.webp_decode_options,
+            &mut |frame, final_rows| {
+                if u32::from(frame.width) != width || u32::from(frame.height) != height {
+                    return ControlFlow::Continue(());
+                }
+
+                // Fancy upsampling of a row also uses the chroma samples of the next row.
+                let end = if final_rows == usize::from(frame.height) {
+                    final_rows
+                } else {
+                    final_rows.saturating_sub(1).max(next_row)
+                };
+                let range = next_row..end.min(rows);
+                next_row = range.end;
+
+                if on_rows(frame, range, alpha_chunk.as_mut()).is_break() || next_row == rows {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            },
+        )?;
+        self.vp8_frame_header = Some(frame.header);
+        if u32::from(frame.width) != self.width || u32::from(frame.height) != self.height {
+            return Err(DecodingError::InconsistentImageSizes);
+        }
+        self.vp8_frame = frame;
+
+        Ok(())
+    }
+
+    /// Like [`read_image`](Self::read_image), but writes into a slice of pixels instead of bytes.
+    ///
+    /// `N` must be 4 for images with alpha and 3 otherwise, which makes `buf` directly usable as a
+    /// slice of RGBA or RGB pixels in other APIs.
+    ///
+    /// Fails with `ImageTooLarge` if `buf` doesn't hold exactly `output_buffer_size()` bytes.
+    pub fn read_image_pixels<const N: usize>(
+        &mut self,
+        buf: &mut [[u8; N]],
+    ) -> Result<(), DecodingError> {
+        self.read_image(buf.as_flattened_mut())
+    }
+
+    /// Like [`read_image`](Self::read_image), but packs each pixel into a `u32` as `0xAARRGGBB`,
+    /// the layout many UI toolkits use for their surfaces. Images without alpha are opaque.
+    ///
+    /// The rows of lossy still images are converted from YUV straight into `buf`. Lossless and
+    /// animated images are decoded into a byte buffer first, which their transforms and the
+    /// compositing of frames work on, and then packed into `buf`.
+    ///
+    /// Fails with `ImageTooLarge` if `buf` doesn't hold exactly one `u32` per pixel.
+    pub fn read_image_argb_u32(&mut self, buf: &mut [u32]) -> Result<(), DecodingError> {
+        if Some(buf.len()) != (self.width as usize).checked_mul(self.height as usize) {
+            return Err(DecodingError::ImageTooLarge);
+        }
+
+        if self.is_animated() || self.chunks.contains_key(&WebPRiffChunk::VP8L) {
+            let mut image = vec![
+                0;
+                self.output_buffer_size()
+                    .ok_or(DecodingError::ImageTooLarge)?
+            ];
+            self.read_image(&mut image)?;
+
+            let bytes_per_pixel = if self.has_alpha() { 4 } else { 3 };
+            for (argb, pixel) in buf.iter_mut().zip(image.chunks_exact(bytes_per_pixel)) {
+                let alpha = pixel.get(3).copied().unwrap_or(0xff);
+                *argb = u32::from_be_bytes([alpha, pixel[0], pixel[1], pixel[2]]);
+            }
+            return Ok(());
+        }
+
+        let upsampling = self.webp_decode_options.lossy_upsampling;
+        let premultiply = self.webp_decode_options.premultiply_alpha;
+        self.decode_lossy_rows(
+            self.height as usize,
+            &mut |frame, range, mut alpha_chunk| {
+                let width = usize::from(frame.width);
+                if let Some(alpha_chunk) = &mut alpha_chunk {
+                    alpha_chunk.unfilter_rows(range.clone(), width);
+                }
+                for y in range {
+                    let row = &mut buf[y * width..][..width];
+                    frame.fill_argb_row(row, y, upsampling);
+                    if let Some(alpha_chunk) = &alpha_chunk {
+                        for (argb, &alpha) in row.iter_mut().zip(&alpha_chunk.data[y * width..]) {
+                            *argb = *argb & 0x00ff_ffff | u32::from(alpha) << 24;
+                        }
+                        if premultiply {
+                            alpha_blending::premultiply_argb(row);
+                        }
+                    }
+                }
+                ControlFlow::Continue(())
+            },
+        )?;
+
+        if self.orientation != 1 {
+            extended::apply_orientation(buf, self.width, self.height, 1, self.orientation);
+        }
+        Ok(())
+    }
+
+    /// Returns the raw bytes of the `width` x `height` rectangle at (`x`, `y`) of the image. For
+    /// animated images, this is a region of the first frame.
+    ///
+    /// The rectangle is given in the coordinates of [`read_image`](Self::read_image), so if
+    /// [`set_auto_orient`](Self::set_auto_orient) is enabled it is a region of the displayed image,
+    /// and the output options are applied like in `read_image`. For lossy still images, decoding
+    /// stops after the macroblock rows holding the region and only its pixels are converted to
+    /// RGB. Lossless and animated images are decoded as a whole and cropped.
+    ///
+    /// Fails with `FrameOutsideImage` if the rectangle doesn't lie within `dimensions()`, and with
+    /// `ImageTooLarge` if `buf` is not exactly large enough to hold the region.
+    pub fn read_region(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        buf: &mut [u8],
+    ) -> Result<(), DecodingError> {
+        let (display_width, display_height) = self.dimensions();
+        if u64::from(x) + u64::from(width) > u64::from(display_width)
+            || u64::from(y) + u64::from(height) > u64::from(display_height)
+        {
+            return Err(DecodingError::FrameOutsideImage);
+        }
+
+        let bytes_per_pixel = if self.has_alpha() { 4 } else { 3 };
+        let row_size = width as usize * bytes_per_pixel;
+        if buf.len() != row_size * height as usize {
+            return Err(DecodingError::ImageTooLarge);
+        }
+
+        let (columns, rows) = extended::stored_region(
+            self.width as usize,
+            self.height as usize,
+            self.orientation,
+            x as usize..(x + width) as usize,
+            y as usize..(y + height) as usize,
+        );
+        if self.is_animated() || self.chunks.contains_key(&WebPRiffChunk::VP8L) {
+            let mut image = vec![
+                0;
+                self.output_buffer_size()
+                    .ok_or(DecodingError::ImageTooLarge)?
+            ];
+            self.decode_image(&mut image)?;
+
+            let stride = self.width as usize * bytes_per_pixel;
+            let row_size = columns.len() * bytes_per_pixel;
+            if row_size > 0 {
+                for (row, image_row) in buf
+                    .chunks_exact_mut(row_size)
+                    .zip(image[rows.start * stride..].chunks(stride))
+                {
+                    row.copy_from_slice(&image_row[columns.start * bytes_per_pixel..][..row_size]);
+                }
+            }
+        } else {
+            self.decode_lossy_region(columns.clone(), rows.clone(), buf)?;
+        }
+
+        self.finish_output(buf, columns.len() as u32, rows.len() as u32);
+        Ok(())
+    }
+
+    /// Decodes the stored pixels `columns` of the rows `rows` of a lossy still image into `buf`,
+    /// stopping once the last of the rows is complete.
+    fn decode_lossy_region(
+        &mut self,
+        columns: Range<usize>,
+        rows: Range<usize>,
+        buf: &mut [u8],
+    ) -> Result<(), DecodingError> {
+        let upsampling = self.webp_decode_options.lossy_upsampling;
+        let bytes_per_pixel = if self.has_alpha() { 4 } else { 3 };
+        let row_size = columns.len() * bytes_per_pixel;
+        let mut scratch = Vec::new();
+        self.decode_lossy_rows(rows.end, &mut |frame, range, mut alpha_chunk| {
+            let width = usize::from(frame.width);
+            if let Some(alpha_chunk) = &mut alpha_chunk {
+                alpha_chunk.unfilter_rows(range.clone(), width);
+            }
+            for y in range.start.max(rows.start)..range.end {
+                let row = &mut buf[(y - rows.start) * row_size..][..row_size];
+                if let Some(alpha_chunk) = &alpha_chunk {
+                    frame.fill_rgb_row_columns::<4>(
+                        row,
+                        y,
+                        columns.clone(),
+                        upsampling,
+                        &mut scratch,
+                    );
+                    for (pixel, &alpha) in row
+                        .chunks_exact_mut(4)
+                        .zip(&alpha_chunk.data[y * width..][columns.clone()])
+                    {
+                        pixel[3] = alpha;
+                    }
+                } else {
+                    frame.fill_rgb_row_columns::<3>(
+                        row,
+                        y,
+                        columns.clone(),
+                        upsampling,
+                        &mut scratch,
+                    );
+                }
+            }
+            ControlFlow::Continue(())
+        })
+    }
+
+    /// Returns the raw bytes of the tile at column `tile_x` and row `tile_y` of a grid of
+    /// `tile_size` x `tile_size` tiles covering the image. Tiles at the right and bottom edges are
+    /// cropped to the image, so they may be smaller.
+    ///
+    /// `tile_size` must be a multiple of the 16 pixel macroblock size. The tile is read like a
+    /// region with [`read_region`](Self::read_region), so for lossy still images decoding stops
+    /// after the macroblock rows of the tile.
+    ///
+    /// Fails with `InvalidParameter` if `tile_size` is not a positive multiple of 16, with
+    /// `FrameOutsideImage` if the tile doesn't lie within `dimensions()`, and with
+    /// `ImageTooLarge` if `buf` is not exactly large enough to hold the tile.
+    pub fn read_tile(
+        &mut self,
+        tile_x: usize,
+        tile_y: usize,
+        tile_size: usize,
+        buf: &mut [u8],
+    ) -> Result<(), DecodingError> {
+        if tile_size == 0 || tile_size % 16 != 0 {
+            return Err(DecodingError::InvalidParameter(format!(
+                "Tile size {tile_size} is not a positive multiple of 16"
+            )));
+        }
+
+        let (width, height) = self.dimensions();
+        let (width, height) = (width as usize, height as usize);
+        let tile_start = |tile: usize, size: usize| {
+            tile.checked_mul(tile_size)
+                .filter(|&start| start < size)
+                .ok_or(DecodingError::FrameOutsideImage)
+        };
+        let (x0, y0) = (tile_start(tile_x, width)?, tile_start(tile_y, height)?);
+        let (x1, y1) = ((x0 + tile_size).min(width), (y0 + tile_size).min(height));
+
+        self.read_region(
+            x0 as u32,
+            y0 as u32,
+            (x1 - x0) as u32,
+            (y1 - y0) as u32,
+            buf,
+        )
+    }
+
+    /// Returns the dimensions of the image when downscaled by `scale`.
+    ///
+    /// Partial blocks at the right and bottom edges produce an output pixel of their own, so the
+    /// dimensions are rounded up. Like `dimensions()`, these are the dimensions of the displayed
+    /// image if [`set_auto_orient`](Self::set_auto_orient) is enabled.
+    pub fn scaled_dimensions(&self, scale: ScaleFactor) -> (u32, u32) {
+        let (width, height) = self.dimensions();
+        (
+            width.div_ceil(scale.divisor()),
+            height.div_ceil(scale.divisor()),
+        )
+    }
+
+    /// Returns the raw bytes of the image downscaled by `scale`. For animated images, this is the
+    /// first frame.
+    ///
+    /// Each output pixel is the average of the corresponding block of the full resolution image,
+    /// with colors weighted by their alpha so that transparent pixels don't bleed into their
+    /// neighbors. The output options are applied like in [`read_image`](Self::read_image). The
+    /// rows of lossy still images are added to the averages as they are decoded, so the full
+    /// resolution image is never held in RGB form. Lossless and animated images are decoded into
+    /// a buffer first.
+    ///
+    /// Fails with `ImageTooLarge` if `buf` is not exactly large enough to hold an image of
+    /// `scaled_dimensions(scale)`.
+    pub fn read_image_scaled(
+        &mut self,
+        scale: ScaleFactor,
+        buf: &mut [u8],
+    ) -> Result<(), DecodingError> {
+        let (scaled_width, scaled_height) = self.scaled_dimensions(scale);
+        let bytes_per_pixel = if self.has_alpha() { 4 } else { 3 };
+        if buf.len() != scaled_width as usize * scaled_height as usize * bytes_per_pixel {
+            return Err(DecodingError::ImageTooLarge);
+        }
+
+        // Blocks are aligned to the top left corner of the displayed image
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut averager = BlockAverager::new(
+            width,
+            height,
+            scale.divisor() as usize,
+            bytes_per_pixel,
+            matches!(self.orientation, 2 | 3 | 7 | 8),
+            matches!(self.orientation, 3 | 4 | 6 | 7),
+        );
+        if self.is_animated() || self.chunks.contains_key(&WebPRiffChunk::VP8L) {
+            let mut image = vec![
+                0;
+                self.output_buffer_size()
+                    .ok_or(DecodingError::ImageTooLarge)?
+            ];
+            self.decode_image(&mut image)?;
+            for (y, row) in image.chunks_exact(width * bytes_per_pixel).enumerate() {
+                averager.add_row(y, row, buf);
             }
+        } else {
+            let upsampling = self.webp_decode_options.lossy_upsampling;
+            let mut row = vec![0; width * bytes_per_pixel];
+            self.decode_lossy_rows(height, &mut |frame, range, mut alpha_chunk| {
+                if let Some(alpha_chunk) = &mut alpha_chunk {
+                    alpha_chunk.unfilter_rows(range.clone(), width);
+                }
+                for y in range {
+                    if let Some(alpha_chunk) = &alpha_chunk {
+                        fill_rgba_row(frame, &mut row, y, upsampling, alpha_chunk);
+                    } else {
+                        frame.fill_rgb_row::<3>(&mut row, y, upsampling);
+                    }
+                    averager.add_row(y, &row, buf);
+                }
+                ControlFlow::Continue(())
+            })?;
         }
 
+        let (width, height) = averager.scaled_dimensions();
+        self.finish_output(buf, width as u32, height as u32);
         Ok(())
     }
 
@@ -750,6 +1906,13 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
     ///
     /// Panics if the image is not animated.
     pub fn read_frame(&mut self, buf: &mut [u8]) -> Result<u32, DecodingError> {
+        let duration = self.decode_frame(buf)?;
+        self.finish_output(buf, self.width, self.height);
+        Ok(duration)
+    }
+
+    /// Decodes the next frame like [`read_frame`](Self::read_frame) but in its stored orientation.
+    fn decode_frame(&mut self, buf: &mut [u8]) -> Result<u32, DecodingError> {
         assert!(self.is_animated());
         assert_eq!(Some(buf.len()), self.output_buffer_size());
 
@@ -760,6 +1923,7 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
         let ImageKind::Extended(info) = &self.kind else {
             unreachable!()
         };
+        let background_color = info.background_color;
 
         self.r
             .seek(io::SeekFrom::Start(self.animation.next_frame_start))?;
@@ -782,11 +1946,14 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
         }
         let duration = extended::read_3_bytes(&mut self.r)?;
         let frame_info = self.r.read_u8()?;
+        if frame_info & 0b11111100 != 0 && self.webp_decode_options.reject_reserved_bits {
+            return Err(DecodingError::ReservedBitSet);
+        }
         let use_alpha_blending = frame_info & 0b00000010 == 0;
         let dispose = frame_info & 0b00000001 != 0;
 
         let clear_color = if self.animation.dispose_next_frame {
-            info.background_color
+            background_color
         } else {
             None
         };
@@ -799,16 +1966,29 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
 
         let (frame, frame_has_alpha): (Vec<u8>, bool) = match chunk {
             WebPRiffChunk::VP8 => {
-                let reader = (&mut self.r).take(chunk_size);
-                let raw_frame = Vp8Decoder::decode_frame(reader)?;
-                if u32::from(raw_frame.width) != frame_width
-                    || u32::from(raw_frame.height) != frame_height
-                {
-                    return Err(DecodingError::InconsistentImageSizes);
+                // Some encoders write the `ALPH` chunk after the `VP8 ` chunk instead of before it.
+                let vp8_start = self.r.stream_position()?;
+                let mut alpha_chunk = None;
+                if chunk_size_rounded + 32 <= anmf_size {
+                    self.r
+                        .seek(io::SeekFrom::Start(vp8_start + chunk_size_rounded))?;
+                    let (next_chunk, next_chunk_size, next_chunk_size_rounded) =
+                        read_chunk_header(&mut self.r)?;
+                    if next_chunk == WebPRiffChunk::ALPH {
+                        if chunk_size_rounded + next_chunk_size_rounded + 32 > anmf_size {
+                            return Err(DecodingError::ChunkHeaderInvalid(next_chunk.to_fourcc()));
+                        }
+                        let mut reader = (&mut self.r).take(next_chunk_size);
+                        alpha_chunk = Some(read_alpha_chunk(
+                            &mut reader,
+                            frame_width as u16,
+                            frame_height as u16,
+                        )?);
+                    }
+                    self.r.seek(io::SeekFrom::Start(vp8_start))?;
                 }
-                let mut rgb_frame = vec![0; frame_width as usize * frame_height as usize * 3];
-                raw_frame.fill_rgb(&mut rgb_frame, self.webp_decode_options.lossy_upsampling);
-                (rgb_frame, false)
+
+                self.read_anmf_vp8(chunk_size, frame_width, frame_height, alpha_chunk)?
             }
             WebPRiffChunk::VP8L => {
                 let reader = (&mut self.r).take(chunk_size);
@@ -831,35 +2011,17 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
                 // read opaque
                 self.r.seek(io::SeekFrom::Start(next_chunk_start))?;
                 let (next_chunk, next_chunk_size, _) = read_chunk_header(&mut self.r)?;
-                if chunk_size + next_chunk_size + 32 > anmf_size {
+                if next_chunk != WebPRiffChunk::VP8 || chunk_size + next_chunk_size + 32 > anmf_size
+                {
                     return Err(DecodingError::ChunkHeaderInvalid(next_chunk.to_fourcc()));
                 }
 
-                let frame = Vp8Decoder::decode_frame((&mut self.r).take(next_chunk_size))?;
-
-                let mut rgba_frame = vec![0; frame_width as usize * frame_height as usize * 4];
-                frame.fill_rgba(&mut rgba_frame, self.webp_decode_options.lossy_upsampling);
-
-                for y in 0..frame.height {
-                    for x in 0..frame.width {
-                        let predictor: u8 = get_alpha_predictor(
-                            x.into(),
-                            y.into(),
-                            frame.width.into(),
-                            alpha_chunk.filtering_method,
-                            &rgba_frame,
-                        );
-
-                        let alpha_index =
-                            usize::from(y) * usize::from(frame.width) + usize::from(x);
-                        let buffer_index = alpha_index * 4 + 3;
-
-                        rgba_frame[buffer_index] =
-                            predictor.wrapping_add(alpha_chunk.data[alpha_index]);
-                    }
-                }
-
-                (rgba_frame, true)
+                self.read_anmf_vp8(
+                    next_chunk_size,
+                    frame_width,
+                    frame_height,
+                    Some(alpha_chunk),
+                )?
             }
             _ => return Err(DecodingError::ChunkHeaderInvalid(chunk.to_fourcc())),
         };
@@ -868,7 +2030,7 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
         if self.animation.canvas.is_none() {
             self.animation.canvas = {
                 let mut canvas = vec![0; (self.width * self.height * 4) as usize];
-                if let Some(color) = info.background_color.as_ref() {
+                if let Some(color) = background_color.as_ref() {
                     canvas
                         .chunks_exact_mut(4)
                         .for_each(|c| c.copy_from_slice(color))
@@ -917,8 +2079,56 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
         Ok(duration)
     }
 
+    /// Decodes the `VP8 ` chunk of an animation frame, which starts at the current position, into
+    /// an RGB buffer, or an RGBA one if the frame has an alpha plane.
+    fn read_anmf_vp8(
+        &mut self,
+        chunk_size: u64,
+        frame_width: u32,
+        frame_height: u32,
+        alpha_chunk: Option<AlphaChunk>,
+    ) -> Result<(Vec<u8>, bool), DecodingError> {
+        let mut frame = std::mem::take(&mut self.vp8_frame);
+        Vp8Decoder::decode_frame_into(
+            (&mut self.r).take(chunk_size),
+            &mut frame,
+            &self.webp_decode_options,
+            &mut |_, _| ControlFlow::Continue(()),
+        )?;
+        self.vp8_frame_header = Some(frame.header);
+        if u32::from(frame.width) != frame_width || u32::from(frame.height) != frame_height {
+            return Err(DecodingError::InconsistentImageSizes);
+        }
+
+        let upsampling = self.webp_decode_options.lossy_upsampling;
+        let (width, height) = (frame_width as usize, frame_height as usize);
+        let has_alpha = alpha_chunk.is_some();
+        let image = if let Some(mut alpha_chunk) = alpha_chunk {
+            let mut rgba_frame = vec![0; width * height * 4];
+            fill_rgba_rows(
+                &frame,
+                &mut rgba_frame,
+                0..height,
+                upsampling,
+                &mut alpha_chunk,
+            );
+            rgba_frame
+        } else {
+            let mut rgb_frame = vec![0; width * height * 3];
+            frame.fill_rgb(&mut rgb_frame, upsampling);
+            rgb_frame
+        };
+        self.vp8_frame = frame;
+
+        Ok((image, has_alpha))
+    }
+
     /// Resets the animation to the first frame.
     ///
+    /// This can be called once [`read_frame`](Self::read_frame) returns
+    /// `DecodingError::NoMoreFrames` to play the animation again. The chunks are not parsed again
+    /// and the canvas keeps its allocation.
+    ///
     /// # Panics
     ///
     /// Panics if the image is not animated.
@@ -928,12 +2138,299 @@ impl<R: BufRead + Seek> WebPDecoder<R> {
         self.animation.next_frame = 0;
         self.animation.next_frame_start = self.chunks.get(&WebPRiffChunk::ANMF).unwrap().start - 8;
         self.animation.dispose_next_frame = true;
+        if let Some(canvas) = self.animation.canvas.as_mut() {
+            let ImageKind::Extended(info) = &self.kind else {
+                unreachable!()
+            };
+            let color = info.background_color.unwrap_or([0; 4]);
+            canvas
+                .chunks_exact_mut(4)
+                .for_each(|c| c.copy_from_slice(&color));
+        }
+    }
+
+    /// Positions the animation so that the next call to [`read_frame`](Self::read_frame) returns
+    /// the frame with the given index, counting from zero.
+    ///
+    /// Frames are composited onto the contents of the canvas left by the previous ones, so the
+    /// frames before `index` are decoded again to rebuild the canvas, starting from the first
+    /// frame when seeking backwards. If `index` is not smaller than
+    /// [`num_frames`](Self::num_frames), the method returns `DecodingError::NoMoreFrames` and the
+    /// position is left unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the image is not animated.
+    pub fn seek_frame(&mut self, index: u32) -> Result<(), DecodingError> {
+        assert!(self.is_animated());
+
+        if index >= self.num_frames {
+            return Err(DecodingError::NoMoreFrames);
+        }
+        if index < self.animation.next_frame {
+            self.reset_animation();
+        }
+        if self.animation.next_frame < index {
+            let mut buf = vec![0; self.output_buffer_size().unwrap()];
+            while self.animation.next_frame < index {
+                self.decode_frame(&mut buf)?;
+            }
+        }
+
+        Ok(())
     }
 
     /// Sets the upsampling method that is used in lossy decoding
     pub fn set_lossy_upsampling(&mut self, upsampling_method: UpsamplingMethod) {
         self.webp_decode_options.lossy_upsampling = upsampling_method;
     }
+
+    /// Sets whether the loop filter is applied in lossy decoding
+    pub fn set_loop_filter(&mut self, enabled: bool) {
+        self.webp_decode_options.loop_filter = enabled;
+    }
+
+    /// Sets whether the prediction modes and quantizers of each macroblock are recorded in lossy
+    /// decoding
+    pub fn set_macroblock_info(&mut self, enabled: bool) {
+        self.webp_decode_options.macroblock_info = enabled;
+    }
+
+    /// Sets the index of the macroblock, in raster order, whose coefficients are recorded in
+    /// lossy decoding, or None to record none
+    pub fn set_macroblock_coefficients(&mut self, index: Option<usize>) {
+        self.webp_decode_options.macroblock_coefficients = index;
+    }
+
+    /// Sets whether the methods returning RGBA pixels, like [`read_image`](Self::read_image) and
+    /// [`read_frame`](Self::read_frame), return premultiplied alpha, where each color channel is
+    /// scaled by `alpha / 255` with rounding
+    pub fn set_premultiply_alpha(&mut self, enabled: bool) {
+        self.webp_decode_options.premultiply_alpha = enabled;
+    }
+
+    /// Sets whether truncated lossy image data is concealed instead of returning an error, see
+    /// [`WebPDecodeOptions::error_concealment`]
+    pub fn set_error_concealment(&mut self, enabled: bool) {
+        self.webp_decode_options.error_concealment = enabled;
+    }
+
+    /// Sets the strength from 0 to 100 of the chroma dithering in lossy decoding, see
+    /// [`WebPDecodeOptions::dithering_strength`]
+    pub fn set_dithering_strength(&mut self, strength: u8) {
+        self.webp_decode_options.dithering_strength = strength;
+    }
+
+    /// Sets whether [`read_image`](Self::read_image) and [`read_frame`](Self::read_frame) rotate
+    /// and flip their output according to the orientation in the EXIF metadata, which is how
+    /// cameras mark images that were taken sideways. If enabled, `dimensions()` returns the size
+    /// of the image as it is displayed.
+    ///
+    /// Images without EXIF metadata or with an unreadable orientation are left as they are.
+    /// All methods returning RGB or RGBA pixels return them oriented, and
+    /// [`read_region`](Self::read_region), [`read_tile`](Self::read_tile) and
+    /// [`read_rows`](Self::read_rows) take the coordinates of the displayed image.
+    /// [`read_image_gray_alpha`](Self::read_image_gray_alpha) is oriented as well, while
+    /// [`read_yuv`](Self::read_yuv) returns the planes as they are stored.
+    pub fn set_auto_orient(&mut self, enabled: bool) {
+        self.orientation = if enabled {
+            self.exif_metadata()
+                .ok()
+                .flatten()
+                .and_then(|exif| extended::exif_orientation(&exif))
+                .unwrap_or(1)
+        } else {
+            1
+        };
+    }
+
+    /// Applies the output options to a decoded image or region of `width` x `height` pixels in
+    /// its stored orientation.
+    fn finish_output(&self, buf: &mut [u8], width: u32, height: u32) {
+        if self.webp_decode_options.premultiply_alpha && self.has_alpha() {
+            alpha_blending::premultiply_alpha(buf);
+        }
+
+        if self.orientation != 1 {
+            let bytes_per_pixel = if self.has_alpha() { 4 } else { 3 };
+            extended::apply_orientation(buf, width, height, bytes_per_pixel, self.orientation);
+        }
+    }
+}
+
+/// Returns the width and height of a WebP image held in memory, without decoding any pixels.
+///
+/// This only reads the header of the first image or `VP8X` chunk, which for extended images gives
+/// the canvas size. Unlike [`WebPDecoder::dimensions`], the size is not swapped for images whose
+/// EXIF metadata rotates them.
+pub fn peek_dimensions(data: &[u8]) -> Result<(u32, u32), DecodingError> {
+    let mut r = io::Cursor::new(data);
+    let (WebPRiffChunk::RIFF, _, _) = read_chunk_header(&mut r)? else {
+        return Err(DecodingError::ChunkHeaderInvalid(*b"RIFF"));
+    };
+    match &read_fourcc(&mut r)? {
+        WebPRiffChunk::WEBP => {}
+        fourcc => return Err(DecodingError::WebpSignatureInvalid(fourcc.to_fourcc())),
+    }
+
+    // Like the decoder, skip any chunks preceding the image data.
+    loop {
+        let (chunk, _, chunk_size_rounded) = match read_chunk_header(&mut r) {
+            Err(DecodingError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(DecodingError::ChunkMissing)
+            }
+            result => result?,
+        };
+        match chunk {
+            WebPRiffChunk::VP8 => {
+                r.set_position(r.position() + 3);
+                let mut tag = [0u8; 3];
+                r.read_exact(&mut tag)?;
+                if tag != [0x9d, 0x01, 0x2a] {
+                    return Err(DecodingError::Vp8MagicInvalid(tag));
+                }
+                let w = r.read_u16::<LittleEndian>()?;
+                let h = r.read_u16::<LittleEndian>()?;
+                return Ok((u32::from(w & 0x3FFF), u32::from(h & 0x3FFF)));
+            }
+            WebPRiffChunk::VP8L => {
+                let signature = r.read_u8()?;
+                if signature != 0x2f {
+                    return Err(DecodingError::LosslessSignatureInvalid(signature));
+                }
+                let header = r.read_u32::<LittleEndian>()?;
+                return Ok(((1 + header) & 0x3FFF, (1 + (header >> 14)) & 0x3FFF));
+            }
+            WebPRiffChunk::VP8X => {
+                let info = extended::read_extended_header(&mut r)?;
+                return Ok((info.canvas_width, info.canvas_height));
+            }
+            _ => r.set_position(r.position() + chunk_size_rounded),
+        }
+    }
+}
+
+/// Returns whether a WebP image held in memory is animated, without decoding any pixels.
+///
+/// This only reads the flags of the `VP8X` chunk, so unlike [`WebPDecoder::new`] it neither walks
+/// the frames of an animation nor validates them.
+pub fn is_animated(data: &[u8]) -> Result<bool, DecodingError> {
+    let mut r = io::Cursor::new(data);
+    let (WebPRiffChunk::RIFF, _, _) = read_chunk_header(&mut r)? else {
+        return Err(DecodingError::ChunkHeaderInvalid(*b"RIFF"));
+    };
+    match &read_fourcc(&mut r)? {
+        WebPRiffChunk::WEBP => {}
+        fourcc => return Err(DecodingError::WebpSignatureInvalid(fourcc.to_fourcc())),
+    }
+
+    // Like the decoder, skip any chunks preceding the image data.
+    loop {
+        let (chunk, _, chunk_size_rounded) = match read_chunk_header(&mut r) {
+            Err(DecodingError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(DecodingError::ChunkMissing)
+            }
+            result => result?,
+        };
+        match chunk {
+            WebPRiffChunk::VP8 | WebPRiffChunk::VP8L => return Ok(false),
+            WebPRiffChunk::VP8X => return Ok(extended::read_extended_header(&mut r)?.animation),
+            _ => r.set_position(r.position() + chunk_size_rounded),
+        }
+    }
+}
+
+/// Decodes a WebP image held in memory into RGB pixels.
+///
+/// Returns the width and height of the image together with the pixel data. Any alpha channel is
+/// discarded, and animated images return their first frame.
+pub fn decode_rgb(data: &[u8]) -> Result<(u32, u32, Vec<u8>), DecodingError> {
+    let (width, height, rgba, has_alpha) = decode_to_vec(data)?;
+    if !has_alpha {
+        return Ok((width, height, rgba));
+    }
+
+    let rgb = rgba
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+    Ok((width, height, rgb))
+}
+
+/// Decodes a WebP image held in memory into RGBA pixels.
+///
+/// Returns the width and height of the image together with the pixel data. Images without alpha
+/// are returned as fully opaque, and animated images return their first frame.
+pub fn decode_rgba(data: &[u8]) -> Result<(u32, u32, Vec<u8>), DecodingError> {
+    let (width, height, rgb, has_alpha) = decode_to_vec(data)?;
+    if has_alpha {
+        return Ok((width, height, rgb));
+    }
+
+    let rgba = rgb
+        .chunks_exact(3)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+        .collect();
+    Ok((width, height, rgba))
+}
+
+fn decode_to_vec(data: &[u8]) -> Result<(u32, u32, Vec<u8>, bool), DecodingError> {
+    let mut decoder = WebPDecoder::new(Cursor::new(data))?;
+    let (width, height) = decoder.dimensions();
+    let mut buf = vec![
+        0;
+        decoder
+            .output_buffer_size()
+            .ok_or(DecodingError::ImageTooLarge)?
+    ];
+    decoder.read_image(&mut buf)?;
+    Ok((width, height, buf, decoder.has_alpha()))
+}
+
+/// Converts the rows `rows` of a lossy frame into an rgba buffer. The alpha values are unfiltered
+/// in the plane and stored right after the colors of each row, while the row is still in cache.
+fn fill_rgba_rows(
+    frame: &Frame,
+    buf: &mut [u8],
+    rows: Range<usize>,
+    upsampling: UpsamplingMethod,
+    alpha_chunk: &mut AlphaChunk,
+) {
+    let width = usize::from(frame.width);
+    alpha_chunk.unfilter_rows(rows.clone(), width);
+    for y in rows {
+        fill_rgba_row(
+            frame,
+            &mut buf[y * width * 4..][..width * 4],
+            y,
+            upsampling,
+            alpha_chunk,
+        );
+    }
+}
+
+/// Receives the frame, the range of newly completed rows and the alpha chunk while a lossy still
+/// image is decoded
+type OnLossyRows<'a> =
+    dyn FnMut(&Frame, Range<usize>, Option<&mut AlphaChunk>) -> ControlFlow<()> + 'a;
+
+/// Fills the single rgba output row `y` from the YUV buffers of `frame` and the already unfiltered
+/// alpha plane.
+fn fill_rgba_row(
+    frame: &Frame,
+    row: &mut [u8],
+    y: usize,
+    upsampling: UpsamplingMethod,
+    alpha_chunk: &AlphaChunk,
+) {
+    let width = usize::from(frame.width);
+    frame.fill_rgb_row::<4>(row, y, upsampling);
+    for (pixel, &alpha) in row
+        .chunks_exact_mut(4)
+        .zip(&alpha_chunk.data[y * width..][..width])
+    {
+        pixel[3] = alpha;
+    }
 }
 
 pub(crate) fn range_reader<R: BufRead + Seek>(
@@ -1027,4 +2524,466 @@ mod tests {
         let first_pixel = &data[..RGB_BPP];
         assert!(data.chunks_exact(3).all(|ch| ch.iter().eq(first_pixel)));
     }
+
+    fn riff_chunk(fourcc: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = fourcc.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    /// Wraps the chunks in a RIFF chunk, after the WebP signature
+    fn webp_file(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut webp = b"WEBP".to_vec();
+        for chunk in chunks {
+            webp.extend_from_slice(chunk);
+        }
+        riff_chunk(b"RIFF", &webp)
+    }
+
+    /// 2x2 red VP8 bitstream from `decode_2x2_single_color_image`
+    const RED_2X2_VP8: [u8; 48] = [
+        0xd0, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x02, 0x00, 0x02, 0x00, 0x02, 0x00, 0x34, 0x25, 0xa0,
+        0x02, 0x74, 0xba, 0x01, 0xf8, 0x00, 0x03, 0xb0, 0x00, 0xfe, 0xf0, 0xc4, 0x0b, 0xff, 0x20,
+        0xb9, 0x61, 0x75, 0xc8, 0xd7, 0xff, 0x20, 0x3f, 0xe4, 0x07, 0xfc, 0x80, 0xff, 0xf8, 0xf2,
+        0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn reject_invalid_vp8_version() {
+        // The 2x2 red image with the version set to 4
+        let mut vp8 = RED_2X2_VP8;
+        vp8[0] = (vp8[0] & !0b1110) | (4 << 1);
+        let bytes = webp_file(&[&riff_chunk(b"VP8 ", &vp8)]);
+
+        let mut data = [0; 2 * 2 * RGB_BPP];
+        let mut decoder = WebPDecoder::new(std::io::Cursor::new(bytes)).unwrap();
+        assert!(matches!(
+            decoder.read_image(&mut data),
+            Err(DecodingError::VersionNumberInvalid(4))
+        ));
+    }
+
+    #[test]
+    fn vp8_scale_bits() {
+        // The 2x2 red image with a 5/4 horizontal and 2 vertical upscale requested
+        let mut vp8 = RED_2X2_VP8;
+        vp8[7] |= 1 << 6;
+        vp8[9] |= 3 << 6;
+        let bytes = webp_file(&[&riff_chunk(b"VP8 ", &vp8)]);
+
+        let mut data = [0; 2 * 2 * RGB_BPP];
+        let mut decoder = WebPDecoder::new(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(decoder.dimensions(), (2, 2));
+        decoder.read_image(&mut data).unwrap();
+
+        let header = decoder.frame_header().unwrap();
+        assert_eq!(header.horizontal_scale, 1);
+        assert_eq!(header.vertical_scale, 3);
+    }
+
+    #[test]
+    fn reject_non_keyframe() {
+        // WebP only allows VP8 key frames, so a frame with the key frame bit cleared (it is
+        // inverted in the frame tag) is reported as unsupported rather than decoded.
+        let mut vp8 = RED_2X2_VP8;
+        vp8[0] |= 1;
+        let bytes = webp_file(&[&riff_chunk(b"VP8 ", &vp8)]);
+
+        assert!(matches!(
+            WebPDecoder::new(std::io::Cursor::new(bytes)),
+            Err(DecodingError::UnsupportedFeature(_))
+        ));
+    }
+
+    #[test]
+    fn animated_alpha_frame_size_mismatch() {
+        let vp8 = RED_2X2_VP8;
+
+        // The ANMF frame claims to be 1x1, which doesn't match the 2x2 VP8 bitstream
+        let mut anmf = vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0];
+        anmf.extend(riff_chunk(b"ALPH", &[0, 255]));
+        anmf.extend(riff_chunk(b"VP8 ", &vp8));
+
+        let bytes = webp_file(&[
+            &riff_chunk(b"VP8X", &[0b00010010, 0, 0, 0, 1, 0, 0, 1, 0, 0]),
+            &riff_chunk(b"ANIM", &[0, 0, 0, 0, 0, 0]),
+            &riff_chunk(b"ANMF", &anmf),
+        ]);
+
+        // The alpha plane matches the frame, so only the sizes of the frame are inconsistent
+        let mut decoder = WebPDecoder::new(std::io::Cursor::new(bytes)).unwrap();
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        assert!(matches!(
+            decoder.read_frame(&mut data),
+            Err(DecodingError::InconsistentImageSizes)
+        ));
+    }
+
+    #[test]
+    fn background_color_byte_order() {
+        let mut anmf = vec![0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 0, 0, 100, 0, 0, 0];
+        anmf.extend(riff_chunk(b"VP8 ", &RED_2X2_VP8));
+        let bytes = webp_file(&[
+            &riff_chunk(b"VP8X", &[0b00000010, 0, 0, 0, 1, 0, 0, 1, 0, 0]),
+            &riff_chunk(b"ANIM", &[10, 20, 30, 40, 0, 0]),
+            &riff_chunk(b"ANMF", &anmf),
+        ]);
+
+        let decoder = WebPDecoder::new(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(decoder.background_color_hint(), Some([10, 20, 30, 40]));
+        assert_eq!(decoder.background_color_rgba(), Some([30, 20, 10, 40]));
+        assert_eq!(
+            decoder.animation_params().unwrap().background_color,
+            [30, 20, 10, 40]
+        );
+    }
+
+    #[test]
+    fn animated_frame_outside_canvas() {
+        let read_frame = |canvas: u8, offset: u8| {
+            let mut anmf = vec![offset / 2, 0, 0, 0, 0, 0, 1, 0, 0, 1, 0, 0, 100, 0, 0, 0];
+            anmf.extend(riff_chunk(b"VP8 ", &RED_2X2_VP8));
+
+            let bytes = webp_file(&[
+                &riff_chunk(
+                    b"VP8X",
+                    &[0b00000010, 0, 0, 0, canvas - 1, 0, 0, canvas - 1, 0, 0],
+                ),
+                &riff_chunk(b"ANIM", &[0, 0, 0, 0, 0, 0]),
+                &riff_chunk(b"ANMF", &anmf),
+            ]);
+
+            let mut decoder = WebPDecoder::new(std::io::Cursor::new(bytes)).unwrap();
+            let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+            decoder.read_frame(&mut data)
+        };
+
+        assert!(read_frame(4, 2).is_ok());
+        // The 2x2 frame sticks out of the canvas on the right
+        assert!(matches!(
+            read_frame(3, 2),
+            Err(DecodingError::FrameOutsideImage)
+        ));
+        // The 2x2 frame is larger than the 1x1 canvas
+        assert!(matches!(
+            read_frame(1, 0),
+            Err(DecodingError::FrameOutsideImage)
+        ));
+    }
+
+    #[test]
+    fn still_image_size_mismatch() {
+        // Still images aren't positioned on the canvas, so their size must be that of the canvas
+        let bytes = webp_file(&[
+            &riff_chunk(b"VP8X", &[0, 0, 0, 0, 3, 0, 0, 3, 0, 0]),
+            &riff_chunk(b"VP8 ", &RED_2X2_VP8),
+        ]);
+
+        let mut decoder = WebPDecoder::new(std::io::Cursor::new(bytes)).unwrap();
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        assert!(matches!(
+            decoder.read_image(&mut data),
+            Err(DecodingError::InconsistentImageSizes)
+        ));
+    }
+
+    #[test]
+    fn still_alpha_size_mismatch() {
+        let still_image = |canvas: u8, alph: &[u8]| {
+            let bytes = webp_file(&[
+                &riff_chunk(
+                    b"VP8X",
+                    &[0b00010000, 0, 0, 0, canvas - 1, 0, 0, canvas - 1, 0, 0],
+                ),
+                &riff_chunk(b"ALPH", alph),
+                &riff_chunk(b"VP8 ", &RED_2X2_VP8),
+            ]);
+
+            let mut decoder = WebPDecoder::new(std::io::Cursor::new(bytes)).unwrap();
+            let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+            decoder.read_image(&mut data)
+        };
+
+        assert!(still_image(2, &[0, 255, 255, 255, 255]).is_ok());
+        // The uncompressed alpha plane is missing a pixel of the 2x2 image
+        assert!(matches!(
+            still_image(2, &[0, 255, 255, 255]),
+            Err(DecodingError::AlphaChunkSizeMismatch)
+        ));
+        // The canvas and its alpha plane are a single pixel, which doesn't match the 2x2 VP8
+        // bitstream
+        assert!(matches!(
+            still_image(1, &[0, 255]),
+            Err(DecodingError::InconsistentImageSizes)
+        ));
+    }
+
+    #[test]
+    fn alpha_chunk_after_vp8_chunk() {
+        // The uncompressed alpha plane makes the ALPH chunk odd sized, so a padding byte separates
+        // it from the chunk that follows.
+        let alph = riff_chunk(b"ALPH", &[0, 10, 20, 30, 40]);
+        assert_eq!(alph.len(), 14);
+        let vp8 = riff_chunk(b"VP8 ", &RED_2X2_VP8);
+        let decode = |animated: bool, image_chunks: &[&Vec<u8>]| {
+            let mut webp = b"WEBP".to_vec();
+            if animated {
+                webp.extend(riff_chunk(
+                    b"VP8X",
+                    &[0b00010010, 0, 0, 0, 1, 0, 0, 1, 0, 0],
+                ));
+                webp.extend(riff_chunk(b"ANIM", &[0, 0, 0, 0, 0, 0]));
+                let mut anmf = vec![0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 0, 0, 100, 0, 0, 0];
+                image_chunks.iter().for_each(|c| anmf.extend(*c));
+                webp.extend(riff_chunk(b"ANMF", &anmf));
+            } else {
+                webp.extend(riff_chunk(
+                    b"VP8X",
+                    &[0b00010000, 0, 0, 0, 1, 0, 0, 1, 0, 0],
+                ));
+                image_chunks.iter().for_each(|c| webp.extend(*c));
+            }
+            let bytes = riff_chunk(b"RIFF", &webp);
+
+            let mut decoder = WebPDecoder::new(std::io::Cursor::new(bytes)).unwrap();
+            let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+            if animated {
+                decoder.read_frame(&mut data).unwrap();
+            } else {
+                decoder.read_image(&mut data).unwrap();
+            }
+            data
+        };
+
+        for animated in [false, true] {
+            let canonical = decode(animated, &[&alph, &vp8]);
+            assert_eq!(
+                canonical.chunks_exact(4).map(|p| p[3]).collect::<Vec<_>>(),
+                [10, 20, 30, 40]
+            );
+            assert_eq!(decode(animated, &[&vp8, &alph]), canonical);
+        }
+    }
+
+    #[test]
+    fn skip_unknown_chunks_before_image() {
+        let bytes = webp_file(&[
+            &riff_chunk(b"JUNK", &[1, 2, 3]),
+            &riff_chunk(b"EXIF", &[0; 4]),
+            &riff_chunk(b"VP8 ", &RED_2X2_VP8),
+        ]);
+
+        let mut decoder = WebPDecoder::new(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(decoder.dimensions(), (2, 2));
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut data).unwrap();
+
+        let bytes = webp_file(&[&riff_chunk(b"JUNK", &[1, 2, 3])]);
+        assert!(matches!(
+            WebPDecoder::new(std::io::Cursor::new(bytes)),
+            Err(DecodingError::ChunkMissing)
+        ));
+    }
+
+    #[test]
+    fn loop_filter_applied() {
+        let decode = |vp8: &[u8], loop_filter: bool| {
+            let bytes = webp_file(&[&riff_chunk(b"VP8 ", vp8)]);
+            let mut decoder = WebPDecoder::new(std::io::Cursor::new(bytes)).unwrap();
+            decoder.set_loop_filter(loop_filter);
+            let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+            decoder.read_image(&mut data).unwrap();
+            (
+                decoder.frame_header().unwrap().filter_level,
+                decoder.loop_filter_applied(),
+            )
+        };
+
+        assert_eq!(decode(&RED_2X2_VP8, true), (8, true));
+        assert_eq!(decode(&RED_2X2_VP8, false), (8, false));
+
+        // A 2x2 image encoded with a filter strength of zero
+        let unfiltered = [
+            0x54, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x02, 0x00, 0x02, 0x00, 0x00, 0x00, 0x34, 0x25,
+            0xa0, 0x00, 0x04, 0x33, 0x80, 0x00, 0xfe, 0xef, 0x76, 0x97, 0xff, 0xfd, 0x83, 0xbf,
+            0xff, 0xd0, 0x77, 0xff, 0xfa, 0x0e, 0xff, 0x4e, 0x00, 0x00,
+        ];
+        assert_eq!(decode(&unfiltered, true), (0, false));
+    }
+
+    #[test]
+    fn ignore_chunks_after_image() {
+        let decode = |trailing: &[u8]| {
+            let bytes = webp_file(&[&riff_chunk(b"VP8 ", &RED_2X2_VP8), trailing]);
+            let mut decoder = WebPDecoder::new(std::io::Cursor::new(bytes)).unwrap();
+            let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+            decoder.read_image(&mut data).unwrap();
+            (decoder.dimensions(), data)
+        };
+
+        // Simple files have a single image chunk, so a second one is ignored like any other chunk.
+        let expected = decode(&[]);
+        assert_eq!(decode(&riff_chunk(b"JUNK", &[1, 2, 3])), expected);
+        assert_eq!(decode(&riff_chunk(b"VP8 ", &[0; 5])), expected);
+    }
+
+    #[test]
+    fn riff_size_is_validated() {
+        let bytes = webp_file(&[&riff_chunk(b"VP8 ", &RED_2X2_VP8)]);
+
+        // Trailing data after the RIFF chunk is ignored
+        let mut trailing = bytes.clone();
+        trailing.extend_from_slice(&[0; 5]);
+        WebPDecoder::new(std::io::Cursor::new(trailing)).unwrap();
+
+        // The RIFF chunk claims to be longer than the data
+        let mut long_riff = bytes.clone();
+        long_riff[4] += 2;
+        assert!(matches!(
+            WebPDecoder::new(std::io::Cursor::new(long_riff)),
+            Err(DecodingError::InvalidChunkSize)
+        ));
+
+        // The VP8 chunk extends past the end of the RIFF chunk
+        let mut long_vp8 = bytes;
+        long_vp8.extend_from_slice(&[0; 2]);
+        long_vp8[16] += 2;
+        assert!(matches!(
+            WebPDecoder::new(std::io::Cursor::new(long_vp8)),
+            Err(DecodingError::InvalidChunkSize)
+        ));
+    }
+
+    #[test]
+    fn reject_reserved_bits() {
+        let decode = |vp8x_flags: u8, vp8x_reserved: u8, anmf_flags: u8, strict: bool| {
+            let mut anmf = vec![0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 0, 0, 100, 0, 0, anmf_flags];
+            anmf.extend(riff_chunk(b"VP8 ", &RED_2X2_VP8));
+
+            let bytes = webp_file(&[
+                &riff_chunk(
+                    b"VP8X",
+                    &[
+                        0b00000010 | vp8x_flags,
+                        vp8x_reserved,
+                        0,
+                        0,
+                        1,
+                        0,
+                        0,
+                        1,
+                        0,
+                        0,
+                    ],
+                ),
+                &riff_chunk(b"ANIM", &[0, 0, 0, 0, 0, 0]),
+                &riff_chunk(b"ANMF", &anmf),
+            ]);
+
+            let options = WebPDecodeOptions {
+                reject_reserved_bits: strict,
+                ..Default::default()
+            };
+            let mut decoder = WebPDecoder::new_with_options(std::io::Cursor::new(bytes), options)?;
+            let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+            decoder.read_frame(&mut data)
+        };
+
+        for strict in [false, true] {
+            assert!(decode(0, 0, 0, strict).is_ok());
+            assert!(decode(0, 0, 0b10, strict).is_ok());
+        }
+        for (vp8x_flags, vp8x_reserved, anmf_flags) in [
+            (0b10000000, 0, 0),
+            (0b00000001, 0, 0),
+            (0, 1, 0),
+            (0, 0, 0b100),
+        ] {
+            assert!(decode(vp8x_flags, vp8x_reserved, anmf_flags, false).is_ok());
+            assert!(matches!(
+                decode(vp8x_flags, vp8x_reserved, anmf_flags, true),
+                Err(DecodingError::ReservedBitSet)
+            ));
+        }
+    }
+
+    #[test]
+    fn lenient_riff_size() {
+        let bytes = webp_file(&[&riff_chunk(b"VP8 ", &RED_2X2_VP8)]);
+        let options = WebPDecodeOptions {
+            lenient_riff_size: true,
+            ..Default::default()
+        };
+        let decode = |bytes: &[u8]| {
+            let mut decoder =
+                WebPDecoder::new_with_options(std::io::Cursor::new(bytes), options.clone())?;
+            let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+            decoder.read_image(&mut data)?;
+            Ok::<_, DecodingError>(data)
+        };
+        let expected = decode(&bytes).unwrap();
+
+        let mut zero_riff = bytes.clone();
+        zero_riff[4..8].fill(0);
+        assert!(matches!(
+            WebPDecoder::new(std::io::Cursor::new(&zero_riff)),
+            Err(DecodingError::InvalidChunkSize)
+        ));
+        assert_eq!(decode(&zero_riff).unwrap(), expected);
+
+        let mut long_riff = bytes.clone();
+        long_riff[4] += 20;
+        assert_eq!(decode(&long_riff).unwrap(), expected);
+
+        let mut short_riff = bytes.clone();
+        short_riff[4] -= 6;
+        assert!(matches!(
+            WebPDecoder::new(std::io::Cursor::new(&short_riff)),
+            Err(DecodingError::InvalidChunkSize)
+        ));
+        assert_eq!(decode(&short_riff).unwrap(), expected);
+
+        // Truncated image data is still an error
+        let mut truncated = zero_riff;
+        truncated.truncate(bytes.len() - 10);
+        assert!(matches!(
+            decode(&truncated),
+            Err(DecodingError::InvalidChunkSize)
+        ));
+    }
+
+    #[test]
+    fn decoding_error_equality() {
+        let eof = || DecodingError::from(io::Error::from(io::ErrorKind::UnexpectedEof));
+        assert_eq!(eof(), eof());
+        assert_ne!(eof(), io::Error::other("other").into());
+        assert_eq!(DecodingError::ChunkMissing, DecodingError::ChunkMissing);
+        assert_ne!(DecodingError::ChunkMissing, DecodingError::ImageTooLarge);
+        assert_eq!(
+            DecodingError::VersionNumberInvalid(4),
+            DecodingError::VersionNumberInvalid(4)
+        );
+        assert_ne!(
+            DecodingError::VersionNumberInvalid(4),
+            DecodingError::VersionNumberInvalid(5)
+        );
+        assert_ne!(
+            DecodingError::VersionNumberInvalid(4),
+            DecodingError::LosslessSignatureInvalid(4)
+        );
+        assert_ne!(
+            DecodingError::UnsupportedFeature("a".to_owned()),
+            DecodingError::UnsupportedFeature("b".to_owned())
+        );
+
+        let bytes = webp_file(&[&riff_chunk(b"JUNK", &[])]);
+        assert_eq!(
+            WebPDecoder::new(std::io::Cursor::new(bytes)).err(),
+            Some(DecodingError::ChunkMissing)
+        );
+    }
 }
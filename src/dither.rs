@@ -0,0 +1,112 @@
+//! Dithering of the chroma planes of lossy images
+//!
+//! Coarsely quantized chroma turns smooth gradients into visible bands. Adding a little noise to
+//! the flat chroma blocks breaks them up. The noise, and which blocks receive it, are the same as
+//! in libwebp, so the output matches that of `dwebp -dither`.
+
+/// Chroma quantizer indices from which on no dithering is applied
+const MAX_DITHER_QUANT: usize = 12;
+
+/// The dithering amplitude for each chroma AC quantizer index, in multiples of an eighth of the
+/// strength
+const QUANT_TO_DITHER_AMP: [u8; MAX_DITHER_QUANT] = [8, 7, 6, 4, 4, 2, 2, 2, 1, 1, 1, 1];
+
+/// Amplitudes below this one don't change any pixel
+pub(crate) const MIN_DITHER_AMP: u8 = 4;
+
+const RANDOM_TABLE_SIZE: usize = 55;
+
+const RANDOM_TABLE: [u32; RANDOM_TABLE_SIZE] = [
+    0x0de15230, 0x03b31886, 0x775faccb, 0x1c88626a, 0x68385c55, 0x14b3b828, 0x4a85fef8, 0x49ddb84b,
+    0x64fcf397, 0x5c550289, 0x4a290000, 0x0d7ec1da, 0x5940b7ab, 0x5492577d, 0x4e19ca72, 0x38d38c69,
+    0x0c01ee65, 0x32a1755f, 0x5437f652, 0x5abb2c32, 0x0faa57b1, 0x73f533e7, 0x685feeda, 0x7563cce2,
+    0x6e990e83, 0x4730a7ed, 0x4fc0d9c6, 0x496b153c, 0x4f1403fa, 0x541afb0c, 0x73990b32, 0x26d7cb1c,
+    0x6fcc3706, 0x2cbb77d8, 0x75762f2a, 0x6425ccdd, 0x24b35461, 0x0a7d8715, 0x220414a8, 0x141ebf67,
+    0x56b41583, 0x73e502e3, 0x44cab16f, 0x28264d42, 0x73baaefb, 0x0a50ebed, 0x1d6ab6fb, 0x0d3ad40b,
+    0x35db3b68, 0x2b081e83, 0x77ce6b95, 0x5181e5f0, 0x78853bbc, 0x009f9494, 0x27e5ed3c,
+];
+
+/// Returns the dithering amplitude of a segment, given the dithering strength from 0 to 100 and
+/// the unclamped chroma AC quantizer index of the segment
+pub(crate) fn dither_amplitude(strength: u8, uv_quant: i32) -> u8 {
+    let strength = u32::from(strength.min(100)) * 255 / 100;
+    match usize::try_from(uv_quant.max(0)) {
+        Ok(index) if index < MAX_DITHER_QUANT => {
+            ((strength * u32::from(QUANT_TO_DITHER_AMP[index])) >> 3) as u8
+        }
+        _ => 0,
+    }
+}
+
+/// Knuth's subtractive random number generator, seeded with a fixed table
+pub(crate) struct Dithering {
+    table: [u32; RANDOM_TABLE_SIZE],
+    index1: usize,
+    index2: usize,
+}
+
+impl Dithering {
+    pub(crate) fn new() -> Self {
+        Self {
+            table: RANDOM_TABLE,
+            index1: 0,
+            index2: 31,
+        }
+    }
+
+    /// Returns a random value centered on 128, which deviates from it by up to `amp / 2`
+    fn next(&mut self, amp: u8) -> i32 {
+        // The table holds 31 bit values, so this is the difference modulo 2^31.
+        let diff = self.table[self.index1].wrapping_sub(self.table[self.index2]) & 0x7fff_ffff;
+        self.table[self.index1] = diff;
+        self.index1 = (self.index1 + 1) % RANDOM_TABLE_SIZE;
+        self.index2 = (self.index2 + 1) % RANDOM_TABLE_SIZE;
+
+        // Keep the top 8 of the 31 random bits, as a signed value.
+        let diff = (diff << 1) as i32 >> 24;
+        ((diff * i32::from(amp)) >> 8) + 128
+    }
+
+    /// Adds noise to the 8x8 block at the start of `buf`, whose rows are `stride` bytes apart
+    pub(crate) fn dither_block(&mut self, buf: &mut [u8], stride: usize, amp: u8) {
+        let mut noise = [0i32; 64];
+        for n in noise.iter_mut() {
+            *n = self.next(amp);
+        }
+
+        for (row, noise) in buf.chunks_mut(stride).zip(noise.chunks_exact(8)) {
+            for (pixel, n) in row[..8].iter_mut().zip(noise) {
+                *pixel = (i32::from(*pixel) + ((n - 128 + 8) >> 4)).clamp(0, 255) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dither_amplitude_depends_on_quantizer() {
+        assert_eq!(dither_amplitude(0, 0), 0);
+        assert_eq!(dither_amplitude(100, -5), 255);
+        assert_eq!(dither_amplitude(100, 3), 127);
+        assert_eq!(dither_amplitude(50, 0), 127);
+        assert_eq!(dither_amplitude(255, 0), 255);
+        assert_eq!(dither_amplitude(100, 12), 0);
+    }
+
+    #[test]
+    fn dither_noise_is_bounded() {
+        let mut dithering = Dithering::new();
+        for _ in 0..1000 {
+            let n = dithering.next(255);
+            assert!((0..256).contains(&n));
+        }
+
+        let mut block = [100u8; 64];
+        dithering.dither_block(&mut block, 8, 255);
+        assert!(block.iter().all(|&p| (92..=108).contains(&p)));
+        assert!(block.iter().any(|&p| p != 100));
+    }
+}
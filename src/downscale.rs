@@ -0,0 +1,126 @@
+//! Downscaling of decoded images by averaging blocks of pixels
+//!
+//! The rows of the full resolution image are accumulated one at a time, so it never has to be
+//! held in memory as a whole. Colors of pixels with alpha are weighted by their alpha, as if the
+//! image was premultiplied, so transparent pixels don't bleed their color into the output.
+
+/// Averages the blocks of `divisor` x `divisor` pixels of an image whose rows are added in top to
+/// bottom order
+///
+/// Partial blocks at the edges produce an output pixel of their own. They are at the right and
+/// bottom edges, unless the image is flipped later on, in which case they are moved to the left or
+/// top edge so they end up at the right and bottom edges of the displayed image.
+pub(crate) struct BlockAverager {
+    width: usize,
+    height: usize,
+    divisor: usize,
+    bytes_per_pixel: usize,
+    flip_x: bool,
+    flip_y: bool,
+    /// The sums of the colors and alpha, and the number of pixels, of the current row of blocks
+    sums: Vec<[u32; 5]>,
+}
+
+impl BlockAverager {
+    pub(crate) fn new(
+        width: usize,
+        height: usize,
+        divisor: usize,
+        bytes_per_pixel: usize,
+        flip_x: bool,
+        flip_y: bool,
+    ) -> Self {
+        Self {
+            width,
+            height,
+            divisor,
+            bytes_per_pixel,
+            flip_x,
+            flip_y,
+            sums: vec![[0; 5]; width.div_ceil(divisor)],
+        }
+    }
+
+    /// Returns the dimensions of the downscaled image
+    pub(crate) fn scaled_dimensions(&self) -> (usize, usize) {
+        (
+            self.width.div_ceil(self.divisor),
+            self.height.div_ceil(self.divisor),
+        )
+    }
+
+    /// Returns the index of the block holding the pixel `i` of an axis of `size` pixels
+    fn block(&self, i: usize, size: usize, flip: bool) -> usize {
+        if flip {
+            size.div_ceil(self.divisor) - 1 - (size - 1 - i) / self.divisor
+        } else {
+            i / self.divisor
+        }
+    }
+
+    /// Adds the row `y` of the image, writing a row of the downscaled image into `buf` once the
+    /// blocks of the row are complete
+    pub(crate) fn add_row(&mut self, y: usize, row: &[u8], buf: &mut [u8]) {
+        for (x, pixel) in row.chunks_exact(self.bytes_per_pixel).enumerate() {
+            let block = self.block(x, self.width, self.flip_x);
+            let sum = &mut self.sums[block];
+            let alpha = pixel.get(3).map_or(1, |&alpha| u32::from(alpha));
+            for (sum, &value) in sum.iter_mut().zip(&pixel[..3]) {
+                *sum += u32::from(value) * alpha;
+            }
+            sum[3] += alpha;
+            sum[4] += 1;
+        }
+
+        let block_y = self.block(y, self.height, self.flip_y);
+        if y + 1 < self.height && self.block(y + 1, self.height, self.flip_y) == block_y {
+            return;
+        }
+
+        let row_size = self.sums.len() * self.bytes_per_pixel;
+        let output = &mut buf[block_y * row_size..][..row_size];
+        for (pixel, sum) in output
+            .chunks_exact_mut(self.bytes_per_pixel)
+            .zip(&mut self.sums)
+        {
+            let [red, green, blue, alpha, count] = *sum;
+            for (value, sum) in pixel.iter_mut().zip([red, green, blue]) {
+                *value = (sum + alpha / 2).checked_div(alpha).unwrap_or(0) as u8;
+            }
+            if let Some(value) = pixel.get_mut(3) {
+                *value = ((alpha + count / 2) / count) as u8;
+            }
+            *sum = [0; 5];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transparent_pixels_are_ignored() {
+        let mut averager = BlockAverager::new(3, 2, 2, 4, false, false);
+        let mut buf = vec![0; 2 * 4];
+        averager.add_row(0, &[200, 0, 0, 255, 0, 200, 0, 0, 10, 20, 30, 40], &mut buf);
+        averager.add_row(1, &[0, 0, 200, 0, 0, 0, 0, 0, 10, 20, 30, 40], &mut buf);
+        assert_eq!(buf, [200, 0, 0, 64, 10, 20, 30, 40]);
+
+        let mut averager = BlockAverager::new(1, 1, 2, 4, false, false);
+        averager.add_row(0, &[1, 2, 3, 0], &mut buf[..4]);
+        assert_eq!(buf[..4], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn flipped_axes_have_partial_blocks_first() {
+        let mut averager = BlockAverager::new(3, 3, 2, 3, true, true);
+        assert_eq!(averager.scaled_dimensions(), (2, 2));
+        let mut buf = vec![0; 2 * 2 * 3];
+        averager.add_row(0, &[10, 10, 10, 20, 20, 20, 30, 30, 30], &mut buf);
+        assert_eq!(buf[..6], [10, 10, 10, 25, 25, 25]);
+        averager.add_row(1, &[0, 0, 0, 0, 0, 0, 0, 0, 0], &mut buf);
+        averager.add_row(2, &[40, 40, 40, 60, 60, 60, 80, 80, 80], &mut buf);
+        assert_eq!(buf[6..], [20, 20, 20, 35, 35, 35]);
+    }
+}
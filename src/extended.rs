@@ -2,6 +2,7 @@ use super::lossless::LosslessDecoder;
 use crate::decoder::DecodingError;
 use byteorder_lite::ReadBytesExt;
 use std::io::{BufRead, Read};
+use std::ops::Range;
 
 use crate::alpha_blending::do_alpha_blending;
 
@@ -20,6 +21,9 @@ pub(crate) struct WebPExtendedInfo {
 
     pub(crate) background_color: Option<[u8; 4]>,
     pub(crate) background_color_hint: [u8; 4],
+
+    /// Whether any of the bits the spec reserves in the header is set
+    pub(crate) reserved_bits_set: bool,
 }
 
 /// Composites a frame onto a canvas.
@@ -63,41 +67,14 @@ pub(crate) fn composite_frame(
 
     // clear rectangle occupied by previous frame
     if let Some(clear_color) = clear_color {
-        match (frame_is_full_size, frame_has_alpha) {
-            (true, true) => {
-                for pixel in canvas.chunks_exact_mut(4) {
-                    pixel.copy_from_slice(&clear_color);
-                }
-            }
-            (true, false) => {
-                for pixel in canvas.chunks_exact_mut(3) {
-                    pixel.copy_from_slice(&clear_color[..3]);
-                }
-            }
-            (false, true) => {
-                for y in 0..previous_frame_height as usize {
-                    for x in 0..previous_frame_width as usize {
-                        let canvas_index = ((x + previous_frame_offset_x as usize)
-                            + (y + previous_frame_offset_y as usize) * canvas_width as usize)
-                            * 4;
-
-                        let output = &mut canvas[canvas_index..][..4];
-                        output.copy_from_slice(&clear_color);
-                    }
-                }
-            }
-            (false, false) => {
-                for y in 0..previous_frame_height as usize {
-                    for x in 0..previous_frame_width as usize {
-                        // let frame_index = (x + y * frame_width as usize) * 4;
-                        let canvas_index = ((x + previous_frame_offset_x as usize)
-                            + (y + previous_frame_offset_y as usize) * canvas_width as usize)
-                            * 3;
-
-                        let output = &mut canvas[canvas_index..][..3];
-                        output.copy_from_slice(&clear_color[..3]);
-                    }
-                }
+        for y in 0..previous_frame_height as usize {
+            let canvas_index = (previous_frame_offset_x as usize
+                + (y + previous_frame_offset_y as usize) * canvas_width as usize)
+                * 4;
+
+            let output = &mut canvas[canvas_index..][..previous_frame_width as usize * 4];
+            for pixel in output.chunks_exact_mut(4) {
+                pixel.copy_from_slice(&clear_color);
             }
         }
     }
@@ -147,7 +124,9 @@ pub(crate) fn composite_frame(
     }
 }
 
-pub(crate) fn get_alpha_predictor(
+/// Returns the prediction of the alpha value at (`x`, `y`) from the already unfiltered values in
+/// `image_slice`, which holds `BPP` bytes per pixel with the alpha value in the last one.
+pub(crate) fn get_alpha_predictor<const BPP: usize>(
     x: usize,
     y: usize,
     width: usize,
@@ -161,10 +140,10 @@ pub(crate) fn get_alpha_predictor(
                 0
             } else if x == 0 {
                 let index = (y - 1) * width + x;
-                image_slice[index * 4 + 3]
+                image_slice[index * BPP + BPP - 1]
             } else {
                 let index = y * width + x - 1;
-                image_slice[index * 4 + 3]
+                image_slice[index * BPP + BPP - 1]
             }
         }
         FilteringMethod::Vertical => {
@@ -172,10 +151,10 @@ pub(crate) fn get_alpha_predictor(
                 0
             } else if y == 0 {
                 let index = y * width + x - 1;
-                image_slice[index * 4 + 3]
+                image_slice[index * BPP + BPP - 1]
             } else {
                 let index = (y - 1) * width + x;
-                image_slice[index * 4 + 3]
+                image_slice[index * BPP + BPP - 1]
             }
         }
         FilteringMethod::Gradient => {
@@ -183,21 +162,21 @@ pub(crate) fn get_alpha_predictor(
                 (0, 0) => (0, 0, 0),
                 (0, y) => {
                     let above_index = (y - 1) * width + x;
-                    let val = image_slice[above_index * 4 + 3];
+                    let val = image_slice[above_index * BPP + BPP - 1];
                     (val, val, val)
                 }
                 (x, 0) => {
                     let before_index = y * width + x - 1;
-                    let val = image_slice[before_index * 4 + 3];
+                    let val = image_slice[before_index * BPP + BPP - 1];
                     (val, val, val)
                 }
                 (x, y) => {
                     let left_index = y * width + x - 1;
-                    let left = image_slice[left_index * 4 + 3];
+                    let left = image_slice[left_index * BPP + BPP - 1];
                     let top_index = (y - 1) * width + x;
-                    let top = image_slice[top_index * 4 + 3];
+                    let top = image_slice[top_index * BPP + BPP - 1];
                     let top_left_index = (y - 1) * width + x - 1;
-                    let top_left = image_slice[top_left_index * 4 + 3];
+                    let top_left = image_slice[top_left_index * BPP + BPP - 1];
 
                     (left, top, top_left)
                 }
@@ -220,8 +199,9 @@ pub(crate) fn read_extended_header<R: Read>(
     let xmp_metadata = chunk_flags & 0b00000100 != 0;
     let animation = chunk_flags & 0b00000010 != 0;
 
-    // reserved bytes are ignored
-    let _reserved_bytes = read_3_bytes(reader)?;
+    // reserved bits are only checked in strict decoding
+    let reserved_bytes = read_3_bytes(reader)?;
+    let reserved_bits_set = chunk_flags & 0b11000001 != 0 || reserved_bytes != 0;
 
     let canvas_width = read_3_bytes(reader)? + 1;
     let canvas_height = read_3_bytes(reader)? + 1;
@@ -241,6 +221,7 @@ pub(crate) fn read_extended_header<R: Read>(
         canvas_height,
         background_color_hint: [0; 4],
         background_color: None,
+        reserved_bits_set,
     };
 
     Ok(info)
@@ -261,6 +242,21 @@ pub(crate) struct AlphaChunk {
     pub(crate) data: Vec<u8>,
 }
 
+impl AlphaChunk {
+    /// Undoes the filtering of the rows `rows` of the alpha plane in place. The rows above them
+    /// must have been unfiltered already.
+    pub(crate) fn unfilter_rows(&mut self, rows: Range<usize>, width: usize) {
+        for y in rows {
+            for x in 0..width {
+                let predictor =
+                    get_alpha_predictor::<1>(x, y, width, self.filtering_method, &self.data);
+                let alpha = &mut self.data[y * width + x];
+                *alpha = predictor.wrapping_add(*alpha);
+            }
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub(crate) enum FilteringMethod {
     None,
@@ -312,8 +308,13 @@ pub(crate) fn read_alpha_chunk<R: BufRead>(
         }
         green
     } else {
-        let mut framedata = vec![0; width as usize * height as usize];
-        reader.read_exact(&mut framedata)?;
+        // An undersized plane would leave pixels of the image without alpha.
+        let size = usize::from(width) * usize::from(height);
+        let mut framedata = Vec::with_capacity(size);
+        reader.take(size as u64).read_to_end(&mut framedata)?;
+        if framedata.len() != size {
+            return Err(DecodingError::AlphaChunkSizeMismatch);
+        }
         framedata
     };
 
@@ -325,3 +326,357 @@ pub(crate) fn read_alpha_chunk<R: BufRead>(
 
     Ok(chunk)
 }
+
+/// Returns the orientation tag of an EXIF chunk, from 1 to 8, or None if it has none.
+///
+/// The chunk holds a TIFF structure, though some encoders keep the `Exif\0\0` prefix of the JPEG
+/// APP1 segment in front of it.
+pub(crate) fn exif_orientation(exif: &[u8]) -> Option<u8> {
+    let tiff = exif.strip_prefix(b"Exif\0\0").unwrap_or(exif);
+    let big_endian = match tiff.get(..4)? {
+        b"II*\0" => false,
+        b"MM\0*" => true,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let read_u32 = |offset: usize| {
+        let bytes = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+
+    let ifd = read_u32(4)? as usize;
+    let entries = read_u16(ifd)?;
+    for i in 0..usize::from(entries) {
+        let entry = ifd + 2 + i * 12;
+        // The orientation is a single SHORT value stored inline
+        if read_u16(entry)? == 0x0112 && read_u16(entry + 2)? == 3 {
+            let orientation = read_u16(entry + 8)?;
+            return (1..=8).contains(&orientation).then_some(orientation as u8);
+        }
+    }
+
+    None
+}
+
+/// Rotates and flips a `width` x `height` image in place, so that an image stored with the EXIF
+/// orientation `orientation` is displayed upright. Orientations 5 to 8 swap width and height.
+///
+/// Each pixel takes `bytes_per_pixel` elements of `buf`, which is 1 for pixels packed into a
+/// `u32`.
+pub(crate) fn apply_orientation<T: Copy>(
+    buf: &mut [T],
+    width: u32,
+    height: u32,
+    bytes_per_pixel: usize,
+    orientation: u8,
+) {
+    let (width, height) = (width as usize, height as usize);
+    let transposed_width = if orientation > 4 { height } else { width };
+    let source = buf.to_vec();
+
+    for y in 0..height {
+        for x in 0..width {
+            let (dest_x, dest_y) = match orientation {
+                2 => (width - 1 - x, y),
+                3 => (width - 1 - x, height - 1 - y),
+                4 => (x, height - 1 - y),
+                5 => (y, x),
+                6 => (height - 1 - y, x),
+                7 => (height - 1 - y, width - 1 - x),
+                8 => (y, width - 1 - x),
+                _ => (x, y),
+            };
+
+            let source_index = (y * width + x) * bytes_per_pixel;
+            let dest_index = (dest_y * transposed_width + dest_x) * bytes_per_pixel;
+            buf[dest_index..][..bytes_per_pixel]
+                .copy_from_slice(&source[source_index..][..bytes_per_pixel]);
+        }
+    }
+}
+
+/// Returns the columns and rows of a `width` x `height` image stored with the EXIF orientation
+/// `orientation` that [`apply_orientation`] moves into the displayed rectangle of `columns` and
+/// `rows`. Orientations 5 to 8 swap columns and rows.
+pub(crate) fn stored_region(
+    width: usize,
+    height: usize,
+    orientation: u8,
+    columns: Range<usize>,
+    rows: Range<usize>,
+) -> (Range<usize>, Range<usize>) {
+    let flip = |range: Range<usize>, size: usize| size - range.end..size - range.start;
+    match orientation {
+        2 => (flip(columns, width), rows),
+        3 => (flip(columns, width), flip(rows, height)),
+        4 => (columns, flip(rows, height)),
+        5 => (rows, columns),
+        6 => (rows, flip(columns, height)),
+        7 => (flip(rows, width), flip(columns, height)),
+        8 => (flip(rows, width), columns),
+        _ => (columns, rows),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 3;
+    const HEIGHT: usize = 2;
+
+    #[rustfmt::skip]
+    const ALPHA: [u8; WIDTH * HEIGHT] = [
+        200, 250,  10,
+        255, 100, 128,
+    ];
+
+    /// Undoes the filtering of an alpha plane the same way the decoder does, one row at a time.
+    fn unfilter(filtering_method: FilteringMethod, filtered: &[u8]) -> Vec<u8> {
+        let mut alpha_chunk = AlphaChunk {
+            _preprocessing: false,
+            filtering_method,
+            data: filtered.to_vec(),
+        };
+        for y in 0..HEIGHT {
+            alpha_chunk.unfilter_rows(y..y + 1, WIDTH);
+        }
+        alpha_chunk.data
+    }
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const CLEAR: [u8; 4] = [0, 0, 255, 0];
+
+    fn canvas_pixel(canvas: &[u8], x: usize, y: usize) -> [u8; 4] {
+        canvas[(y * 4 + x) * 4..][..4].try_into().unwrap()
+    }
+
+    #[test]
+    fn composite_frame_disposes_previous_rectangle() {
+        let mut canvas = RED.repeat(16);
+        let frame = [0, 255, 0];
+
+        // A 1x1 opaque frame at (0, 0), after a 2x2 frame at (1, 1) that was disposed.
+        composite_frame(
+            &mut canvas,
+            4,
+            4,
+            Some(CLEAR),
+            &frame,
+            0,
+            0,
+            1,
+            1,
+            false,
+            true,
+            2,
+            2,
+            1,
+            1,
+        );
+
+        for y in 0..4 {
+            for x in 0..4 {
+                let expected = match (x, y) {
+                    (0, 0) => [0, 255, 0, 255],
+                    (1..=2, 1..=2) => CLEAR,
+                    _ => RED,
+                };
+                assert_eq!(canvas_pixel(&canvas, x, y), expected, "pixel ({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn composite_frame_blend_and_overwrite() {
+        // 2x1 frame with a transparent pixel followed by a half transparent one.
+        let frame = [0, 255, 0, 0, 0, 255, 0, 128];
+
+        let mut blended = RED.repeat(16);
+        composite_frame(
+            &mut blended,
+            4,
+            4,
+            None,
+            &frame,
+            1,
+            2,
+            2,
+            1,
+            true,
+            true,
+            0,
+            0,
+            0,
+            0,
+        );
+        assert_eq!(canvas_pixel(&blended, 1, 2), RED);
+        let pixel = canvas_pixel(&blended, 2, 2);
+        assert_eq!(pixel[3], 255);
+        assert!(pixel[0] > 0 && pixel[1] > 0);
+
+        let mut overwritten = RED.repeat(16);
+        composite_frame(
+            &mut overwritten,
+            4,
+            4,
+            None,
+            &frame,
+            1,
+            2,
+            2,
+            1,
+            true,
+            false,
+            0,
+            0,
+            0,
+            0,
+        );
+        assert_eq!(canvas_pixel(&overwritten, 1, 2), [0, 255, 0, 0]);
+        assert_eq!(canvas_pixel(&overwritten, 2, 2), [0, 255, 0, 128]);
+
+        // Everything outside of the frame rectangle is untouched.
+        for canvas in [&blended, &overwritten] {
+            for y in 0..4 {
+                for x in 0..4 {
+                    if y != 2 || !(1..=2).contains(&x) {
+                        assert_eq!(canvas_pixel(canvas, x, y), RED);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn alpha_filtering_none() {
+        assert_eq!(unfilter(FilteringMethod::None, &ALPHA), ALPHA);
+    }
+
+    #[test]
+    fn alpha_filtering_horizontal() {
+        // First row predicts from the left, first column from above.
+        #[rustfmt::skip]
+        let filtered = [
+            200,  50,  16,
+             55, 101,  28,
+        ];
+        assert_eq!(unfilter(FilteringMethod::Horizontal, &filtered), ALPHA);
+    }
+
+    #[test]
+    fn alpha_filtering_vertical() {
+        // First row predicts from the left, all other rows from above.
+        #[rustfmt::skip]
+        let filtered = [
+            200,  50,  16,
+             55, 106, 118,
+        ];
+        assert_eq!(unfilter(FilteringMethod::Vertical, &filtered), ALPHA);
+    }
+
+    #[test]
+    fn alpha_filtering_gradient() {
+        // Interior pixels predict from `left + top - top_left`, which is clamped to 255
+        // at (1, 1) and to 0 at (2, 1).
+        #[rustfmt::skip]
+        let filtered = [
+            200,  50,  16,
+             55, 101, 128,
+        ];
+        assert_eq!(unfilter(FilteringMethod::Gradient, &filtered), ALPHA);
+    }
+
+    /// Builds a little endian TIFF structure whose first IFD holds the given entries
+    fn exif(entries: &[(u16, u16, u32)]) -> Vec<u8> {
+        let mut tiff = b"Exif\0\0II*\0\x08\0\0\0".to_vec();
+        tiff.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for &(tag, kind, value) in entries {
+            tiff.extend_from_slice(&tag.to_le_bytes());
+            tiff.extend_from_slice(&kind.to_le_bytes());
+            tiff.extend_from_slice(&1u32.to_le_bytes());
+            tiff.extend_from_slice(&value.to_le_bytes());
+        }
+        tiff
+    }
+
+    #[test]
+    fn exif_orientation_tag() {
+        assert_eq!(
+            exif_orientation(&exif(&[(0x0110, 2, 0), (0x0112, 3, 6)])),
+            Some(6)
+        );
+        assert_eq!(exif_orientation(&exif(&[(0x0110, 2, 0)])), None);
+        assert_eq!(exif_orientation(&exif(&[(0x0112, 3, 9)])), None);
+        assert_eq!(exif_orientation(&exif(&[(0x0112, 3, 3)])[6..]), Some(3));
+        assert_eq!(
+            exif_orientation(b"MM\0*\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x08"),
+            Some(8)
+        );
+        assert_eq!(exif_orientation(&exif(&[(0x0112, 3, 6)])[..20]), None);
+        assert_eq!(exif_orientation(b"JUNK"), None);
+    }
+
+    #[test]
+    fn apply_all_orientations() {
+        #[rustfmt::skip]
+        let expected: [[u8; 6]; 8] = [
+            [1, 2, 3, 4, 5, 6],
+            [3, 2, 1, 6, 5, 4],
+            [6, 5, 4, 3, 2, 1],
+            [4, 5, 6, 1, 2, 3],
+            [1, 4, 2, 5, 3, 6],
+            [4, 1, 5, 2, 6, 3],
+            [6, 3, 5, 2, 4, 1],
+            [3, 6, 2, 5, 1, 4],
+        ];
+        for (orientation, expected) in (1..=8).zip(expected) {
+            let mut image = [1, 2, 3, 4, 5, 6];
+            apply_orientation(&mut image, 3, 2, 1, orientation);
+            assert_eq!(image, expected, "orientation {orientation}");
+        }
+    }
+
+    #[test]
+    fn stored_region_of_all_orientations() {
+        let (width, height) = (5, 3);
+        let stored: Vec<u8> = (0..15).collect();
+        for orientation in 1..=8 {
+            let mut displayed = stored.clone();
+            apply_orientation(&mut displayed, 5, 3, 1, orientation);
+            let displayed_width = if orientation > 4 { height } else { width };
+
+            // Rows 1..3 and the columns from 1 to the second to last column
+            let (columns, rows) = (1..displayed_width - 1, 1..3);
+            let (stored_columns, stored_rows) =
+                stored_region(width, height, orientation, columns.clone(), rows.clone());
+            let mut region: Vec<u8> = stored_rows
+                .clone()
+                .flat_map(|y| stored[y * width..][stored_columns.clone()].to_vec())
+                .collect();
+            apply_orientation(
+                &mut region,
+                stored_columns.len() as u32,
+                stored_rows.len() as u32,
+                1,
+                orientation,
+            );
+
+            let expected: Vec<u8> = rows
+                .flat_map(|y| displayed[y * displayed_width..][columns.clone()].to_vec())
+                .collect();
+            assert_eq!(region, expected, "orientation {orientation}");
+        }
+    }
+}
@@ -10,12 +10,16 @@
 extern crate test;
 
 pub use self::decoder::{
-    DecodingError, LoopCount, UpsamplingMethod, WebPDecodeOptions, WebPDecoder,
+    decode_rgb, decode_rgba, is_animated, peek_dimensions, AnimationParams, DecodingError,
+    LoopCount, ScaleFactor, UpsamplingMethod, WebPColorType, WebPDecodeOptions, WebPDecoder,
+    WebPFormat, YuvImage,
 };
 pub use self::encoder::{ColorType, EncoderParams, EncodingError, WebPEncoder};
 
 mod alpha_blending;
 mod decoder;
+mod dither;
+mod downscale;
 mod encoder;
 mod extended;
 mod huffman;
@@ -3,6 +3,12 @@ const CONST1: i64 = 20091;
 /// 16 bit fixed point version of sin(PI/8) * sqrt(2)
 const CONST2: i64 = 35468;
 
+/// Inverse transform of a block that only has a DC coefficient, which is the same value everywhere
+pub(crate) fn idct4x4_dc(block: &mut [i32; 16]) {
+    let dc = (block[0] + 4) >> 3;
+    block.fill(dc);
+}
+
 // inverse discrete cosine transform, used in decoding
 pub(crate) fn idct4x4(block: &mut [i32]) {
     // The intermediate results may overflow the types, so we stretch the type.
@@ -163,6 +169,7 @@ pub(crate) fn dct4x4(block: &mut [i32; 16]) {
 
 #[cfg(test)]
 mod tests {
+    #![allow(clippy::clone_on_copy)]
     use super::*;
 
     #[test]
@@ -181,4 +188,18 @@ mod tests {
 
         assert_eq!(BLOCK, inverse_dct_block);
     }
+
+    #[test]
+    fn test_idct_dc_only() {
+        for dc in (-2048 * 157..=2048 * 157).step_by(7) {
+            let mut block = [0; 16];
+            block[0] = dc;
+            let mut full = block;
+
+            idct4x4_dc(&mut block);
+            idct4x4(&mut full);
+
+            assert_eq!(block, full, "DC coefficient {dc}");
+        }
+    }
 }
@@ -12,14 +12,18 @@
 
 use byteorder_lite::{LittleEndian, ReadBytesExt};
 use std::default::Default;
-use std::io::Read;
+use std::io::{self, Read};
+use std::ops::{ControlFlow, Range};
 
-use crate::decoder::{DecodingError, UpsamplingMethod};
+use crate::decoder::{DecodingError, UpsamplingMethod, WebPDecodeOptions};
+use crate::dither::{dither_amplitude, Dithering, MIN_DITHER_AMP};
 use crate::vp8_common::*;
+pub use crate::vp8_common::{ChromaMode, IntraMode, LumaMode};
 use crate::vp8_prediction::*;
-use crate::yuv;
+use crate::yuv::{self, Argb, Bytes, PixelLayout};
 
 use super::vp8_arithmetic_decoder::ArithmeticDecoder;
+pub use super::vp8_arithmetic_decoder::Checkpoint;
 use super::{loop_filter, transform};
 
 #[derive(Clone, Copy)]
@@ -62,6 +66,14 @@ const fn tree_nodes_from<const N: usize, const M: usize>(
     let mut nodes = [TreeNode::UNINIT; M];
     let mut i = 0;
     while i < M {
+        // Branches must point to later nodes, which guarantees that reading the tree terminates
+        let mut j = 2 * i;
+        while j < 2 * i + 2 {
+            if tree[j] > 0 && (tree[j] as usize <= 2 * i || tree[j] as usize >= N) {
+                panic!("invalid tree branch");
+            }
+            j += 1;
+        }
         nodes[i].left = TreeNode::prepare_branch(tree[2 * i]);
         nodes[i].right = TreeNode::prepare_branch(tree[2 * i + 1]);
         nodes[i].prob = probs[i];
@@ -123,6 +135,7 @@ struct MacroBlock {
     segmentid: u8,
     coeffs_skipped: bool,
     non_zero_dct: bool,
+    non_zero_uv_ac: bool,
 }
 
 /// A Representation of the last decoded video frame
@@ -143,8 +156,6 @@ pub struct Frame {
     /// The red plane of the frame
     pub vbuf: Vec<u8>,
 
-    pub(crate) version: u8,
-
     /// Indicates whether this frame is intended for display
     pub for_display: bool,
 
@@ -153,10 +164,134 @@ pub struct Frame {
     /// of the VP8 Specification
     pub pixel_type: u8,
 
-    // Section 9.4 and 15
-    pub(crate) filter_type: bool, //if true uses simple filter // if false uses normal filter
-    pub(crate) filter_level: u8,
-    pub(crate) sharpness_level: u8,
+    pub(crate) header: FrameHeader,
+    pub(crate) macroblock_info: Vec<MacroblockInfo>,
+    pub(crate) macroblock_quant: Vec<MacroblockQuant>,
+    pub(crate) partition_usage: Vec<(usize, usize)>,
+    pub(crate) partition_sizes: Vec<usize>,
+    /// Whether decoding stopped at truncated data and the remaining macroblocks were concealed
+    pub(crate) incomplete: bool,
+    /// Whether the loop filter was applied to at least one macroblock
+    pub(crate) loop_filtered: bool,
+    pub(crate) macroblock_coefficients: Option<MacroblockCoefficients>,
+}
+
+/// Prediction modes of a decoded macroblock
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MacroblockInfo {
+    /// The prediction mode of the luma plane, where `B` predicts each 4x4 subblock on its own
+    pub luma_mode: LumaMode,
+
+    /// The prediction mode of both chroma planes
+    pub chroma_mode: ChromaMode,
+
+    /// The prediction modes of the 16 luma subblocks in raster order, if the luma mode is `B`
+    pub subblock_modes: Option<[IntraMode; 16]>,
+}
+
+/// Quantizer step sizes a decoded macroblock was dequantized with, after applying its segment
+/// and the deltas of the frame header
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MacroblockQuant {
+    /// The segment of the macroblock, which is 0 if segmentation is disabled
+    pub segment: u8,
+
+    /// The step size of the luma DC coefficients, only used by macroblocks predicted per subblock
+    pub y_dc: i16,
+
+    /// The step size of the luma AC coefficients
+    pub y_ac: i16,
+
+    /// The step size of the DC coefficients of the second order luma block
+    pub y2_dc: i16,
+
+    /// The step size of the AC coefficients of the second order luma block
+    pub y2_ac: i16,
+
+    /// The step size of the chroma DC coefficients
+    pub uv_dc: i16,
+
+    /// The step size of the chroma AC coefficients
+    pub uv_ac: i16,
+}
+
+/// Dequantized DCT coefficients of a decoded macroblock, before the inverse transforms
+///
+/// The coefficients of each 4x4 block are in raster order, with the DC coefficient first.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MacroblockCoefficients {
+    /// The second order block holding the DC coefficients of the luma blocks, which macroblocks
+    /// predicted per subblock don't have
+    pub y2: Option<[i32; 16]>,
+
+    /// The 16 luma blocks in raster order. If there is a second order block, their DC
+    /// coefficients are the output of its inverse Walsh-Hadamard transform.
+    pub y: [[i32; 16]; 16],
+
+    /// The 4 blocks of the blue plane in raster order
+    pub u: [[i32; 16]; 4],
+
+    /// The 4 blocks of the red plane in raster order
+    pub v: [[i32; 16]; 4],
+}
+
+/// Header information of a VP8 frame
+///
+/// See Sections 9.2 to 9.6 of the VP8 specification.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FrameHeader {
+    /// The version number of the bitstream, which selects the reconstruction filter and the type
+    /// of loop filter
+    pub version: u8,
+
+    /// Indicates whether this frame is intended for display
+    pub for_display: bool,
+
+    /// The upscaling of the width requested for display: none, 5/4, 5/3 or 2 for the values 0 to 3
+    ///
+    /// Like libwebp, the decoder ignores this and always outputs the coded dimensions.
+    pub horizontal_scale: u8,
+
+    /// The upscaling of the height requested for display, see `horizontal_scale`
+    pub vertical_scale: u8,
+
+    /// Whether the decoder must clamp the reconstructed pixel values (0) or the encoder
+    /// guarantees that no clamping is necessary (1)
+    ///
+    /// The color space bit preceding it is always 0, as other values are rejected. Like libwebp,
+    /// the decoder clamps in either case, which doesn't change pixels that need no clamping.
+    pub clamping_type: u8,
+
+    /// Whether the macroblocks are split into segments with their own quantizer and loop filter
+    /// levels
+    pub segmentation_enabled: bool,
+
+    /// Whether the simple loop filter is used instead of the normal one
+    pub simple_filter: bool,
+
+    /// The loop filter level of the frame, from 0 to 63
+    pub filter_level: u8,
+
+    /// The loop filter sharpness of the frame, from 0 to 7
+    pub sharpness_level: u8,
+
+    /// The number of DCT token partitions, either 1, 2, 4 or 8
+    pub num_partitions: u8,
+
+    /// The base quantizer index of the frame, from 0 to 127
+    pub quantizer_index: u8,
+
+    /// The deltas applied to the quantizer index for the Y DC, Y2 DC, Y2 AC, UV DC and UV AC
+    /// coefficients, in that order
+    pub quantizer_deltas: [i8; 5],
+
+    /// The probability out of 256 that a macroblock has coefficients, if each macroblock signals
+    /// whether it has none and its residual data is skipped (`mb_no_skip_coeff`)
+    pub prob_skip_false: Option<u8>,
 }
 
 impl Frame {
@@ -173,6 +308,12 @@ impl Frame {
         }
     }
 
+    /// Returns the row `y` of the luma plane, without the padding to whole macroblocks
+    pub(crate) fn luma_row(&self, y: usize) -> &[u8] {
+        let stride = usize::from(self.buffer_width());
+        &self.ybuf[y * stride..][..usize::from(self.width)]
+    }
+
     /// Fills an rgb buffer from the YUV buffers
     pub(crate) fn fill_rgb(&self, buf: &mut [u8], upsampling_method: UpsamplingMethod) {
         const BPP: usize = 3;
@@ -203,35 +344,109 @@ impl Frame {
         }
     }
 
-    /// Fills an rgba buffer from the YUV buffers
-    pub(crate) fn fill_rgba(&self, buf: &mut [u8], upsampling_method: UpsamplingMethod) {
-        const BPP: usize = 4;
+    /// Fills the output rows `rows` of an rgb or rgba buffer holding the whole image from the YUV
+    /// buffers
+    pub(crate) fn fill_rgb_rows<const BPP: usize>(
+        &self,
+        buf: &mut [u8],
+        rows: Range<usize>,
+        upsampling_method: UpsamplingMethod,
+    ) {
+        let row_size = usize::from(self.width) * BPP;
+        for y in rows {
+            self.fill_rgb_row::<BPP>(&mut buf[y * row_size..][..row_size], y, upsampling_method);
+        }
+    }
 
-        match upsampling_method {
+    /// Fills a buffer holding the single rgb or rgba output row `y` from the YUV buffers
+    pub(crate) fn fill_rgb_row<const BPP: usize>(
+        &self,
+        row_buffer: &mut [u8],
+        y: usize,
+        upsampling_method: UpsamplingMethod,
+    ) {
+        self.fill_span::<Bytes<BPP>>(row_buffer, y, 0..usize::from(self.width), upsampling_method);
+    }
+
+    /// Fills a buffer holding the single output row `y` with opaque pixels packed into a `u32` as
+    /// `0xAARRGGBB` from the YUV buffers
+    pub(crate) fn fill_argb_row(
+        &self,
+        row_buffer: &mut [u32],
+        y: usize,
+        upsampling_method: UpsamplingMethod,
+    ) {
+        self.fill_span::<Argb>(row_buffer, y, 0..usize::from(self.width), upsampling_method);
+    }
+
+    /// Fills a buffer holding the pixels `columns` of the rgb or rgba output row `y` from the YUV
+    /// buffers, converting little more than those pixels
+    ///
+    /// The pixels at the ends of the span handed to the YUV conversion are interpolated like
+    /// those at the edges of the image, so the span is widened by a pixel on either side where
+    /// needed and converted into `scratch` first.
+    pub(crate) fn fill_rgb_row_columns<const BPP: usize>(
+        &self,
+        row_buffer: &mut [u8],
+        y: usize,
+        columns: Range<usize>,
+        upsampling_method: UpsamplingMethod,
+        scratch: &mut Vec<u8>,
+    ) {
+        let width = usize::from(self.width);
+        let span = match upsampling_method {
+            // An odd number of pixels, starting at an even column before the first one needed
             UpsamplingMethod::Bilinear => {
-                yuv::fill_rgb_buffer_fancy::<BPP>(
-                    buf,
-                    &self.ybuf,
-                    &self.ubuf,
-                    &self.vbuf,
-                    usize::from(self.width),
-                    usize::from(self.height),
-                    usize::from(self.buffer_width()),
-                );
-            }
-            UpsamplingMethod::Simple => {
-                yuv::fill_rgb_buffer_simple::<BPP>(
-                    buf,
-                    &self.ybuf,
-                    &self.ubuf,
-                    &self.vbuf,
-                    usize::from(self.width),
-                    usize::from(self.chroma_width()),
-                    usize::from(self.buffer_width()),
-                );
+                columns.start.saturating_sub(1) & !1..(columns.end | 1).min(width)
             }
+            UpsamplingMethod::Simple => columns.start & !1..columns.end,
+        };
+        if span == columns {
+            self.fill_span::<Bytes<BPP>>(row_buffer, y, span, upsampling_method);
+            return;
+        }
+
+        scratch.resize(span.len() * BPP, 0);
+        self.fill_span::<Bytes<BPP>>(scratch, y, span.clone(), upsampling_method);
+        row_buffer
+            .copy_from_slice(&scratch[(columns.start - span.start) * BPP..][..row_buffer.len()]);
+    }
+
+    /// Fills a buffer with the pixels `columns` of the output row `y`, where `columns` must be a
+    /// span the YUV conversion handles, see [`yuv::fill_rgb_row_fancy`]
+    fn fill_span<P: PixelLayout>(
+        &self,
+        row_buffer: &mut [P::Element],
+        y: usize,
+        columns: Range<usize>,
+        upsampling_method: UpsamplingMethod,
+    ) {
+        match upsampling_method {
+            UpsamplingMethod::Bilinear => yuv::fill_rgb_row_fancy::<P>(
+                row_buffer,
+                y,
+                columns,
+                &self.ybuf,
+                &self.ubuf,
+                &self.vbuf,
+                usize::from(self.width),
+                usize::from(self.height),
+                usize::from(self.buffer_width()),
+            ),
+            UpsamplingMethod::Simple => yuv::fill_rgb_row_simple::<P>(
+                row_buffer,
+                y,
+                columns,
+                &self.ybuf,
+                &self.ubuf,
+                &self.vbuf,
+                usize::from(self.width),
+                usize::from(self.chroma_width()),
+                usize::from(self.buffer_width()),
+            ),
         }
     }
+
     /// Gets the buffer size
     #[must_use]
     pub fn get_buf_size(&self) -> usize {
@@ -239,6 +454,87 @@ impl Frame {
     }
 }
 
+/// The boolean entropy decoder of VP8, as defined by Section 7 of RFC 6386, for decoding the
+/// partitions of a VP8 bitstream piece by piece
+///
+/// Reading past the end of the data yields zero bits and sets [`is_overflow`](Self::is_overflow),
+/// which should be checked after a group of reads.
+pub struct BoolDecoder {
+    decoder: ArithmeticDecoder,
+}
+
+impl BoolDecoder {
+    /// Creates a decoder reading from the start of a partition
+    pub fn new(data: &[u8]) -> Self {
+        let mut chunks = vec![[0; 4]; data.len().div_ceil(4)];
+        chunks.as_mut_slice().as_flattened_mut()[..data.len()].copy_from_slice(data);
+        let mut decoder = ArithmeticDecoder::new();
+        decoder
+            .init(chunks, data.len())
+            .expect("the chunks hold exactly the bytes of the data");
+        Self { decoder }
+    }
+
+    /// Reads a bool which is `false` with a probability of `probability / 256`
+    pub fn read_bool(&mut self, probability: u8) -> bool {
+        let mut res = self.decoder.start_accumulated_result();
+        self.decoder.read_bool(probability).or_accumulate(&mut res)
+    }
+
+    /// Reads a bool with even probabilities
+    pub fn read_flag(&mut self) -> bool {
+        let mut res = self.decoder.start_accumulated_result();
+        self.decoder.read_flag().or_accumulate(&mut res)
+    }
+
+    /// Reads an unsigned `n` bit literal, most significant bit first
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is larger than 8.
+    pub fn read_literal(&mut self, n: u8) -> u8 {
+        assert!(n <= 8, "literals have at most 8 bits");
+        let mut res = self.decoder.start_accumulated_result();
+        self.decoder.read_literal(n).or_accumulate(&mut res)
+    }
+
+    /// Reads an optional signed value as used in the frame header: a flag telling whether the
+    /// value is present, followed by an `n` bit magnitude and a sign flag. Absent values are zero.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is larger than 8.
+    pub fn read_signed(&mut self, n: u8) -> i32 {
+        assert!(n <= 8, "literals have at most 8 bits");
+        let mut res = self.decoder.start_accumulated_result();
+        self.decoder
+            .read_optional_signed_value(n)
+            .or_accumulate(&mut res)
+    }
+
+    /// Returns whether more bits were read than the data holds, in which case the values read
+    /// since are meaningless
+    pub fn is_overflow(&self) -> bool {
+        self.decoder.is_past_eof()
+    }
+
+    /// Returns the number of bytes of the data whose bits have been read
+    pub fn bytes_consumed(&self) -> usize {
+        self.decoder.bytes_consumed()
+    }
+
+    /// Saves the current position, for example before parsing speculatively
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.decoder.checkpoint()
+    }
+
+    /// Returns to a position saved by [`checkpoint`](Self::checkpoint) of this decoder, which
+    /// also clears [`is_overflow`](Self::is_overflow) if it was set since
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        self.decoder.restore(checkpoint);
+    }
+}
+
 /// VP8 Decoder
 ///
 /// Only decodes keyframes
@@ -263,6 +559,15 @@ pub struct Vp8Decoder<R> {
     partitions: [ArithmeticDecoder; 8],
     num_partitions: u8,
 
+    skip_loop_filter: bool,
+    record_macroblock_info: bool,
+    /// The index of the macroblock whose coefficients are recorded
+    record_coefficients: Option<usize>,
+    conceal_errors: bool,
+    dithering_strength: u8,
+    dither_amps: [u8; MAX_SEGMENTS],
+    dithering: Option<Dithering>,
+
     segment_tree_nodes: [TreeNode; 3],
     token_probs: Box<TokenProbTreeNodes>,
 
@@ -323,6 +628,14 @@ impl<R: Read> Vp8Decoder<R> {
 
             num_partitions: 1,
 
+            skip_loop_filter: false,
+            record_macroblock_info: false,
+            record_coefficients: None,
+            conceal_errors: false,
+            dithering_strength: 0,
+            dither_amps: [0; MAX_SEGMENTS],
+            dithering: None,
+
             segment_tree_nodes: SEGMENT_TREE_NODE_DEFAULTS,
             token_probs: Box::new(COEFF_PROB_NODES),
 
@@ -361,31 +674,41 @@ impl<R: Read> Vp8Decoder<R> {
     }
 
     fn init_partitions(&mut self, n: usize) -> Result<(), DecodingError> {
-        if n > 1 {
-            let mut sizes = vec![0; 3 * n - 3];
-            self.r.read_exact(sizes.as_mut_slice())?;
-
-            for (i, s) in sizes.chunks(3).enumerate() {
-                let size = { s }
-                    .read_u24::<LittleEndian>()
-                    .expect("Reading from &[u8] can't fail and the chunk is complete");
-
-                let size = size as usize;
-                let mut buf = vec![[0; 4]; size.div_ceil(4)];
-                let bytes: &mut [u8] = buf.as_mut_slice().as_flattened_mut();
-                self.r.read_exact(&mut bytes[..size])?;
-                self.partitions[i].init(buf, size)?;
-            }
-        }
+        let mut sizes = vec![0; 3 * n - 3];
+        self.r.read_exact(sizes.as_mut_slice())?;
 
         let mut buf = Vec::new();
         self.r.read_to_end(&mut buf)?;
-        let size = buf.len();
-        let mut chunks = vec![[0; 4]; size.div_ceil(4)];
-        chunks.as_mut_slice().as_flattened_mut()[..size].copy_from_slice(&buf);
-        self.partitions[n - 1].init(chunks, size)?;
 
-        Ok(())
+        let mut data = buf.as_slice();
+        for (i, s) in sizes.chunks(3).enumerate() {
+            let size = { s }
+                .read_u24::<LittleEndian>()
+                .expect("Reading from &[u8] can't fail and the chunk is complete");
+
+            let mut size = size as usize;
+            if size > data.len() {
+                if !self.conceal_errors {
+                    return Err(DecodingError::BitStreamError);
+                }
+                // The data was cut short, the partitions run into the missing bytes.
+                size = data.len();
+            }
+            let (partition, rest) = data.split_at(size);
+            Self::init_partition(&mut self.partitions[i], partition)?;
+            self.frame.partition_sizes.push(size);
+            data = rest;
+        }
+
+        self.frame.partition_sizes.push(data.len());
+        Self::init_partition(&mut self.partitions[n - 1], data)
+    }
+
+    fn init_partition(partition: &mut ArithmeticDecoder, data: &[u8]) -> Result<(), DecodingError> {
+        let size = data.len();
+        let mut chunks = vec![[0; 4]; size.div_ceil(4)];
+        chunks.as_mut_slice().as_flattened_mut()[..size].copy_from_slice(data);
+        partition.init(chunks, size)
     }
 
     fn read_quantization_indices(&mut self) -> Result<(), DecodingError> {
@@ -406,6 +729,15 @@ impl<R: Read> Vp8Decoder<R> {
         let uvdc_delta = self.b.read_optional_signed_value(4).or_accumulate(&mut res);
         let uvac_delta = self.b.read_optional_signed_value(4).or_accumulate(&mut res);
 
+        self.frame.header.quantizer_index = yac_abs;
+        self.frame.header.quantizer_deltas = [
+            ydc_delta as i8,
+            y2dc_delta as i8,
+            y2ac_delta as i8,
+            uvdc_delta as i8,
+            uvac_delta as i8,
+        ];
+
         let n = if self.segments_enabled {
             MAX_SEGMENTS
         } else {
@@ -439,6 +771,8 @@ impl<R: Read> Vp8Decoder<R> {
             if self.segment[i].uvdc > 132 {
                 self.segment[i].uvdc = 132;
             }
+
+            self.dither_amps[i] = dither_amplitude(self.dithering_strength, base + uvac_delta);
         }
 
         self.b.check(res, ())
@@ -511,8 +845,14 @@ impl<R: Read> Vp8Decoder<R> {
             ));
         }
 
-        self.frame.version = ((tag >> 1) & 7) as u8;
+        self.frame.header.version = ((tag >> 1) & 7) as u8;
+        if self.frame.header.version > 3 {
+            return Err(DecodingError::VersionNumberInvalid(
+                self.frame.header.version,
+            ));
+        }
         self.frame.for_display = (tag >> 4) & 1 != 0;
+        self.frame.header.for_display = self.frame.for_display;
 
         let first_partition_size = tag >> 5;
 
@@ -528,6 +868,8 @@ impl<R: Read> Vp8Decoder<R> {
 
         self.frame.width = w & 0x3FFF;
         self.frame.height = h & 0x3FFF;
+        self.frame.header.horizontal_scale = (w >> 14) as u8;
+        self.frame.header.vertical_scale = (h >> 14) as u8;
 
         self.top = init_top_macroblocks(self.frame.width as usize);
         // Almost always the first macro block, except when non exists (i.e. `width == 0`)
@@ -536,10 +878,19 @@ impl<R: Read> Vp8Decoder<R> {
         self.mbwidth = self.frame.width.div_ceil(16);
         self.mbheight = self.frame.height.div_ceil(16);
 
-        self.frame.ybuf =
-            vec![0u8; usize::from(self.mbwidth) * 16 * usize::from(self.mbheight) * 16];
-        self.frame.ubuf = vec![0u8; usize::from(self.mbwidth) * 8 * usize::from(self.mbheight) * 8];
-        self.frame.vbuf = vec![0u8; usize::from(self.mbwidth) * 8 * usize::from(self.mbheight) * 8];
+        // The planes may still hold the allocations of a previously decoded frame
+        let luma_size = usize::from(self.mbwidth) * 16 * usize::from(self.mbheight) * 16;
+        let chroma_size = usize::from(self.mbwidth) * 8 * usize::from(self.mbheight) * 8;
+        for (plane, size) in [
+            (&mut self.frame.ybuf, luma_size),
+            (&mut self.frame.ubuf, chroma_size),
+            (&mut self.frame.vbuf, chroma_size),
+        ] {
+            plane.clear();
+            plane.resize(size, 0);
+        }
+        self.macroblocks =
+            Vec::with_capacity(usize::from(self.mbwidth) * usize::from(self.mbheight));
 
         self.top_border_y = vec![127u8; self.frame.width as usize + 4 + 16];
         self.left_border_y = vec![129u8; 1 + 16];
@@ -554,7 +905,15 @@ impl<R: Read> Vp8Decoder<R> {
         let size = first_partition_size as usize;
         let mut buf = vec![[0; 4]; size.div_ceil(4)];
         let bytes: &mut [u8] = buf.as_mut_slice().as_flattened_mut();
-        self.r.read_exact(&mut bytes[..size])?;
+        let size = if self.conceal_errors {
+            let read = read_up_to(&mut self.r, &mut bytes[..size])?;
+            // The partition may be cut short, possibly by a corrupt size field.
+            buf.truncate(read.div_ceil(4));
+            read
+        } else {
+            self.r.read_exact(&mut bytes[..size])?;
+            size
+        };
 
         // initialise binary decoder
         self.b.init(buf, size)?;
@@ -562,19 +921,21 @@ impl<R: Read> Vp8Decoder<R> {
         let mut res = self.b.start_accumulated_result();
         let color_space = self.b.read_literal(1).or_accumulate(&mut res);
         self.frame.pixel_type = self.b.read_literal(1).or_accumulate(&mut res);
+        self.frame.header.clamping_type = self.frame.pixel_type;
 
         if color_space != 0 {
             return Err(DecodingError::ColorSpaceInvalid(color_space));
         }
 
         self.segments_enabled = self.b.read_flag().or_accumulate(&mut res);
+        self.frame.header.segmentation_enabled = self.segments_enabled;
         if self.segments_enabled {
             self.read_segment_updates()?;
         }
 
-        self.frame.filter_type = self.b.read_flag().or_accumulate(&mut res);
-        self.frame.filter_level = self.b.read_literal(6).or_accumulate(&mut res);
-        self.frame.sharpness_level = self.b.read_literal(3).or_accumulate(&mut res);
+        self.frame.header.simple_filter = self.b.read_flag().or_accumulate(&mut res);
+        self.frame.header.filter_level = self.b.read_literal(6).or_accumulate(&mut res);
+        self.frame.header.sharpness_level = self.b.read_literal(3).or_accumulate(&mut res);
 
         self.loop_filter_adjustments_enabled = self.b.read_flag().or_accumulate(&mut res);
         if self.loop_filter_adjustments_enabled {
@@ -585,6 +946,7 @@ impl<R: Read> Vp8Decoder<R> {
         self.b.check(res, ())?;
 
         self.num_partitions = num_partitions as u8;
+        self.frame.header.num_partitions = self.num_partitions;
         self.init_partitions(num_partitions)?;
 
         self.read_quantization_indices()?;
@@ -602,6 +964,7 @@ impl<R: Read> Vp8Decoder<R> {
             None
         };
         self.b.check(res, ())?;
+        self.frame.header.prob_skip_false = self.prob_skip_false;
         Ok(())
     }
 
@@ -677,7 +1040,8 @@ impl<R: Read> Vp8Decoder<R> {
             LumaMode::B => predict_4x4(&mut ws, stride, &mb.bpred, resdata),
         }
 
-        if mb.luma_mode != LumaMode::B {
+        // The residue is zero in flat areas, where many macroblocks are just predicted.
+        if mb.luma_mode != LumaMode::B && mb.non_zero_dct {
             for y in 0usize..4 {
                 for x in 0usize..4 {
                     let i = x + y * 4;
@@ -742,18 +1106,20 @@ impl<R: Read> Vp8Decoder<R> {
             }
         }
 
-        for y in 0usize..2 {
-            for x in 0usize..2 {
-                let i = x + y * 2;
-                let urb: &[i32; 16] = resdata[16 * 16 + i * 16..][..16].try_into().unwrap();
+        if mb.non_zero_dct {
+            for y in 0usize..2 {
+                for x in 0usize..2 {
+                    let i = x + y * 2;
+                    let urb: &[i32; 16] = resdata[16 * 16 + i * 16..][..16].try_into().unwrap();
 
-                let y0 = 1 + y * 4;
-                let x0 = 1 + x * 4;
-                add_residue(&mut uws, urb, y0, x0, stride);
+                    let y0 = 1 + y * 4;
+                    let x0 = 1 + x * 4;
+                    add_residue(&mut uws, urb, y0, x0, stride);
 
-                let vrb: &[i32; 16] = resdata[20 * 16 + i * 16..][..16].try_into().unwrap();
+                    let vrb: &[i32; 16] = resdata[20 * 16 + i * 16..][..16].try_into().unwrap();
 
-                add_residue(&mut vws, vrb, y0, x0, stride);
+                    add_residue(&mut vws, vrb, y0, x0, stride);
+                }
             }
         }
 
@@ -776,6 +1142,8 @@ impl<R: Read> Vp8Decoder<R> {
         }
     }
 
+    /// Reads the dequantized coefficients of a block, returning the zigzag position after the
+    /// last coefficient token. Blocks for which this is at most 1 have no AC coefficients.
     fn read_coefficients(
         &mut self,
         block: &mut [i32; 16],
@@ -784,7 +1152,7 @@ impl<R: Read> Vp8Decoder<R> {
         complexity: usize,
         dcq: i16,
         acq: i16,
-    ) -> Result<bool, DecodingError> {
+    ) -> Result<usize, DecodingError> {
         // perform bounds checks once up front,
         // so that the compiler doesn't have to insert them in the hot loop below
         assert!(complexity <= 2);
@@ -800,7 +1168,7 @@ impl<R: Read> Vp8Decoder<R> {
         let mut res = decoder.start_accumulated_result();
 
         let mut complexity = complexity;
-        let mut has_coefficients = false;
+        let mut end = first_coeff;
         let mut skip = false;
 
         for i in first_coeff..16usize {
@@ -816,7 +1184,7 @@ impl<R: Read> Vp8Decoder<R> {
 
                 DCT_0 => {
                     skip = true;
-                    has_coefficients = true;
+                    end = i + 1;
                     complexity = 0;
                     continue;
                 }
@@ -859,10 +1227,10 @@ impl<R: Read> Vp8Decoder<R> {
             let zigzag = ZIGZAG[i] as usize;
             block[zigzag] = abs_value * i32::from(if zigzag > 0 { acq } else { dcq });
 
-            has_coefficients = true;
+            end = i + 1;
         }
 
-        decoder.check(res, has_coefficients)
+        decoder.check(res, end)
     }
 
     fn read_residual_data(
@@ -870,6 +1238,7 @@ impl<R: Read> Vp8Decoder<R> {
         mb: &mut MacroBlock,
         mbx: usize,
         p: usize,
+        mut coefficients: Option<&mut MacroblockCoefficients>,
     ) -> Result<[i32; 384], DecodingError> {
         let sindex = mb.segmentid as usize;
         let mut blocks = [0i32; 384];
@@ -884,11 +1253,16 @@ impl<R: Read> Vp8Decoder<R> {
             let mut block = [0i32; 16];
             let dcq = self.segment[sindex].y2dc;
             let acq = self.segment[sindex].y2ac;
-            let n = self.read_coefficients(&mut block, p, plane, complexity as usize, dcq, acq)?;
+            let end =
+                self.read_coefficients(&mut block, p, plane, complexity as usize, dcq, acq)?;
+            let n = end > 0;
 
             self.left.complexity[0] = if n { 1 } else { 0 };
             self.top[mbx].complexity[0] = if n { 1 } else { 0 };
 
+            if let Some(coefficients) = coefficients.as_deref_mut() {
+                coefficients.y2 = Some(block);
+            }
             transform::iwht4x4(&mut block);
 
             for k in 0usize..16 {
@@ -898,6 +1272,7 @@ impl<R: Read> Vp8Decoder<R> {
             plane = Plane::YCoeff1;
         }
 
+        let first_coeff = usize::from(plane == Plane::YCoeff1);
         for y in 0usize..4 {
             let mut left = self.left.complexity[y + 1];
             for x in 0usize..4 {
@@ -909,11 +1284,19 @@ impl<R: Read> Vp8Decoder<R> {
                 let dcq = self.segment[sindex].ydc;
                 let acq = self.segment[sindex].yac;
 
-                let n = self.read_coefficients(block, p, plane, complexity as usize, dcq, acq)?;
+                let end = self.read_coefficients(block, p, plane, complexity as usize, dcq, acq)?;
+                let n = end > first_coeff;
+                if let Some(coefficients) = coefficients.as_deref_mut() {
+                    coefficients.y[i] = *block;
+                }
 
                 if block[0] != 0 || n {
                     mb.non_zero_dct = true;
-                    transform::idct4x4(block);
+                    if end <= 1 {
+                        transform::idct4x4_dc(block);
+                    } else {
+                        transform::idct4x4(block);
+                    }
                 }
 
                 left = if n { 1 } else { 0 };
@@ -938,11 +1321,25 @@ impl<R: Read> Vp8Decoder<R> {
                     let dcq = self.segment[sindex].uvdc;
                     let acq = self.segment[sindex].uvac;
 
-                    let n =
+                    let end =
                         self.read_coefficients(block, p, plane, complexity as usize, dcq, acq)?;
+                    let n = end > 0;
+                    if let Some(coefficients) = coefficients.as_deref_mut() {
+                        let plane = if j == 5 {
+                            &mut coefficients.u
+                        } else {
+                            &mut coefficients.v
+                        };
+                        plane[x + y * 2] = *block;
+                    }
                     if block[0] != 0 || n {
                         mb.non_zero_dct = true;
-                        transform::idct4x4(block);
+                        if end <= 1 {
+                            transform::idct4x4_dc(block);
+                        } else {
+                            mb.non_zero_uv_ac = true;
+                            transform::idct4x4(block);
+                        }
                     }
 
                     left = if n { 1 } else { 0 };
@@ -964,6 +1361,7 @@ impl<R: Read> Vp8Decoder<R> {
         let (filter_level, interior_limit, hev_threshold) = self.calculate_filter_parameters(mb);
 
         if filter_level > 0 {
+            self.frame.loop_filtered = true;
             let mbedge_limit = (filter_level + 2) * 2 + interior_limit;
             let sub_bedge_limit = (filter_level * 2) + interior_limit;
 
@@ -974,7 +1372,7 @@ impl<R: Read> Vp8Decoder<R> {
             //filter across left of macroblock
             if mbx > 0 {
                 //simple loop filtering
-                if self.frame.filter_type {
+                if self.frame.header.simple_filter {
                     for y in 0usize..16 {
                         let y0 = mby * 16 + y;
                         let x0 = mbx * 16;
@@ -1019,7 +1417,7 @@ impl<R: Read> Vp8Decoder<R> {
 
             //filter across vertical subblocks in macroblock
             if do_subblock_filtering {
-                if self.frame.filter_type {
+                if self.frame.header.simple_filter {
                     for x in (4usize..16 - 1).step_by(4) {
                         for y in 0..16 {
                             let y0 = mby * 16 + y;
@@ -1069,7 +1467,7 @@ impl<R: Read> Vp8Decoder<R> {
 
             //filter across top of macroblock
             if mby > 0 {
-                if self.frame.filter_type {
+                if self.frame.header.simple_filter {
                     for x in 0usize..16 {
                         let y0 = mby * 16;
                         let x0 = mbx * 16 + x;
@@ -1123,7 +1521,7 @@ impl<R: Read> Vp8Decoder<R> {
 
             //filter across horizontal subblock edges within the macroblock
             if do_subblock_filtering {
-                if self.frame.filter_type {
+                if self.frame.header.simple_filter {
                     for y in (4usize..16 - 1).step_by(4) {
                         for x in 0..16 {
                             let y0 = mby * 16 + y;
@@ -1184,7 +1582,7 @@ impl<R: Read> Vp8Decoder<R> {
     //return values are the filter level, interior limit and hev threshold
     fn calculate_filter_parameters(&self, macroblock: &MacroBlock) -> (u8, u8, u8) {
         let segment = self.segment[macroblock.segmentid as usize];
-        let mut filter_level = i32::from(self.frame.filter_level);
+        let mut filter_level = i32::from(self.frame.header.filter_level);
 
         // if frame level filter level is 0, we must skip loop filter
         if filter_level == 0 {
@@ -1213,11 +1611,15 @@ impl<R: Read> Vp8Decoder<R> {
         //interior limit
         let mut interior_limit = filter_level;
 
-        if self.frame.sharpness_level > 0 {
-            interior_limit >>= if self.frame.sharpness_level > 4 { 2 } else { 1 };
+        if self.frame.header.sharpness_level > 0 {
+            interior_limit >>= if self.frame.header.sharpness_level > 4 {
+                2
+            } else {
+                1
+            };
 
-            if interior_limit > 9 - self.frame.sharpness_level {
-                interior_limit = 9 - self.frame.sharpness_level;
+            if interior_limit > 9 - self.frame.header.sharpness_level {
+                interior_limit = 9 - self.frame.header.sharpness_level;
             }
         }
 
@@ -1239,56 +1641,210 @@ impl<R: Read> Vp8Decoder<R> {
 
     /// Decodes the current frame
     pub fn decode_frame(r: R) -> Result<Frame, DecodingError> {
-        let decoder = Self::new(r);
-        decoder.decode_frame_()
+        let mut decoder = Self::new(r);
+        decoder.decode_frame_(&mut |_, _| ControlFlow::Continue(()))?;
+        Ok(decoder.frame)
+    }
+
+    /// Decodes the frame like [`Self::decode_frame`], but reuses the plane buffers of `frame`,
+    /// which is overwritten with the result. The loop filter and the recording of macroblock
+    /// modes and quantizers are controlled by `options`.
+    ///
+    /// `on_rows` is called with the partially decoded frame and the number of luma rows from the
+    /// top which are final. The loop filter of a macroblock row modifies the bottom of the row
+    /// above it, so these lag one macroblock row behind reconstruction. Decoding stops early,
+    /// leaving the remaining rows undecoded, if `on_rows` returns `ControlFlow::Break`.
+    pub(crate) fn decode_frame_into(
+        r: R,
+        frame: &mut Frame,
+        options: &WebPDecodeOptions,
+        on_rows: &mut dyn FnMut(&Frame, usize) -> ControlFlow<()>,
+    ) -> Result<(), DecodingError> {
+        let mut decoder = Self::new(r);
+        decoder.frame = std::mem::take(frame);
+        decoder.frame.macroblock_info.clear();
+        decoder.frame.macroblock_quant.clear();
+        decoder.frame.partition_usage.clear();
+        decoder.frame.partition_sizes.clear();
+        decoder.frame.incomplete = false;
+        decoder.frame.loop_filtered = false;
+        decoder.frame.macroblock_coefficients = None;
+        decoder.skip_loop_filter = !options.loop_filter;
+        decoder.record_macroblock_info = options.macroblock_info;
+        decoder.record_coefficients = options.macroblock_coefficients;
+        decoder.conceal_errors = options.error_concealment;
+        decoder.dithering_strength = options.dithering_strength;
+        decoder.decode_frame_(on_rows)?;
+        if decoder.record_macroblock_info {
+            decoder.frame.partition_usage = decoder.partition_usage();
+        }
+        *frame = decoder.frame;
+        Ok(())
     }
 
-    fn decode_frame_(mut self) -> Result<Frame, DecodingError> {
+    fn decode_frame_(
+        &mut self,
+        on_rows: &mut dyn FnMut(&Frame, usize) -> ControlFlow<()>,
+    ) -> Result<(), DecodingError> {
         self.read_frame_header()?;
 
+        if self.dither_amps.iter().any(|&amp| amp != 0) {
+            self.dithering = Some(Dithering::new());
+        }
+
         for mby in 0..self.mbheight as usize {
-            let p = mby % self.num_partitions as usize;
-            self.left = MacroBlock::default();
+            match self.decode_macroblock_row(mby) {
+                Ok(()) => {}
+                Err(DecodingError::BitStreamError) if self.conceal_errors => {
+                    self.conceal_macroblock_rows(mby);
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
 
-            for mbx in 0..self.mbwidth as usize {
-                let mut mb = self.read_macroblock_header(mbx)?;
-                let blocks = if !mb.coeffs_skipped {
-                    self.read_residual_data(&mut mb, mbx, p)?
-                } else {
-                    if mb.luma_mode != LumaMode::B {
-                        self.left.complexity[0] = 0;
-                        self.top[mbx].complexity[0] = 0;
-                    }
+            // Intra prediction only uses the unfiltered borders, so the row can be filtered
+            // right away.
+            if !self.skip_loop_filter {
+                for mbx in 0..self.mbwidth as usize {
+                    let mb = self.macroblocks[mby * self.mbwidth as usize + mbx];
+                    self.loop_filter(mbx, mby, &mb);
+                }
+            }
 
-                    for i in 1usize..9 {
-                        self.left.complexity[i] = 0;
-                        self.top[mbx].complexity[i] = 0;
-                    }
+            if self.dithering.is_some() {
+                self.dither_row(mby);
+            }
 
-                    [0i32; 384]
-                };
+            let final_rows = (mby * 16).min(usize::from(self.frame.height));
+            if on_rows(&self.frame, final_rows).is_break() {
+                return Ok(());
+            }
+        }
+
+        let _ = on_rows(&self.frame, usize::from(self.frame.height));
 
-                self.intra_predict_luma(mbx, mby, &mb, &blocks);
-                self.intra_predict_chroma(mbx, mby, &mb, &blocks);
+        Ok(())
+    }
 
-                self.macroblocks.push(mb);
+    fn decode_macroblock_row(&mut self, mby: usize) -> Result<(), DecodingError> {
+        let p = mby % self.num_partitions as usize;
+        self.left = MacroBlock::default();
+
+        for mbx in 0..self.mbwidth as usize {
+            let mut mb = self.read_macroblock_header(mbx)?;
+            let mut coefficients = (self.record_coefficients
+                == Some(mby * usize::from(self.mbwidth) + mbx))
+            .then(MacroblockCoefficients::default);
+            let blocks = if !mb.coeffs_skipped {
+                self.read_residual_data(&mut mb, mbx, p, coefficients.as_mut())?
+            } else {
+                if let Some(coefficients) = &mut coefficients {
+                    coefficients.y2 = (mb.luma_mode != LumaMode::B).then_some([0; 16]);
+                }
+                if mb.luma_mode != LumaMode::B {
+                    self.left.complexity[0] = 0;
+                    self.top[mbx].complexity[0] = 0;
+                }
+
+                for i in 1usize..9 {
+                    self.left.complexity[i] = 0;
+                    self.top[mbx].complexity[i] = 0;
+                }
+
+                [0i32; 384]
+            };
+
+            if coefficients.is_some() {
+                self.frame.macroblock_coefficients = coefficients;
             }
 
-            self.left_border_y = vec![129u8; 1 + 16];
-            self.left_border_u = vec![129u8; 1 + 8];
-            self.left_border_v = vec![129u8; 1 + 8];
+            self.intra_predict_luma(mbx, mby, &mb, &blocks);
+            self.intra_predict_chroma(mbx, mby, &mb, &blocks);
+
+            if self.record_macroblock_info {
+                self.frame.macroblock_info.push(MacroblockInfo {
+                    luma_mode: mb.luma_mode,
+                    chroma_mode: mb.chroma_mode,
+                    subblock_modes: (mb.luma_mode == LumaMode::B).then_some(mb.bpred),
+                });
+                let segment = &self.segment[usize::from(mb.segmentid)];
+                self.frame.macroblock_quant.push(MacroblockQuant {
+                    segment: mb.segmentid,
+                    y_dc: segment.ydc,
+                    y_ac: segment.yac,
+                    y2_dc: segment.y2dc,
+                    y2_ac: segment.y2ac,
+                    uv_dc: segment.uvdc,
+                    uv_ac: segment.uvac,
+                });
+            }
+            self.macroblocks.push(mb);
         }
 
-        //do loop filtering
-        for mby in 0..self.mbheight as usize {
-            for mbx in 0..self.mbwidth as usize {
-                let mb = self.macroblocks[mby * self.mbwidth as usize + mbx];
-                self.loop_filter(mbx, mby, &mb);
+        self.left_border_y.fill(129);
+        self.left_border_u.fill(129);
+        self.left_border_v.fill(129);
+
+        Ok(())
+    }
+
+    /// Adds noise to the chroma of the macroblocks of a row that have no AC coefficients, which
+    /// breaks up the banding of smooth gradients in heavily quantized images
+    fn dither_row(&mut self, mby: usize) {
+        let Some(dithering) = self.dithering.as_mut() else {
+            return;
+        };
+        let mbwidth = usize::from(self.mbwidth);
+        let chroma_w = mbwidth * 8;
+
+        for mbx in 0..mbwidth {
+            let mb = &self.macroblocks[mby * mbwidth + mbx];
+            if mb.coeffs_skipped || mb.non_zero_uv_ac {
+                continue;
+            }
+            let amp = self.dither_amps[usize::from(mb.segmentid)];
+            if amp < MIN_DITHER_AMP {
+                continue;
             }
+
+            let offset = mby * 8 * chroma_w + mbx * 8;
+            dithering.dither_block(&mut self.frame.ubuf[offset..], chroma_w, amp);
+            dithering.dither_block(&mut self.frame.vbuf[offset..], chroma_w, amp);
         }
+    }
+
+    /// Fills the macroblock rows from `mby` to the bottom of the frame with neutral gray after
+    /// their data turned out to be truncated, and marks the frame as incomplete.
+    fn conceal_macroblock_rows(&mut self, mby: usize) {
+        let mbwidth = usize::from(self.mbwidth);
+        self.frame.ybuf[mby * 16 * mbwidth * 16..].fill(128);
+        self.frame.ubuf[mby * 8 * mbwidth * 8..].fill(128);
+        self.frame.vbuf[mby * 8 * mbwidth * 8..].fill(128);
+        self.frame.incomplete = true;
+    }
+
+    /// Returns the number of bytes consumed and the size of the first partition, followed by
+    /// those of each DCT token partition.
+    fn partition_usage(&self) -> Vec<(usize, usize)> {
+        std::iter::once(&self.b)
+            .chain(&self.partitions[..usize::from(self.num_partitions)])
+            .map(|b| (b.bytes_consumed(), b.len()))
+            .collect()
+    }
+}
 
-        Ok(self.frame)
+/// Reads into `buf` until it is full or the reader is exhausted, returning the number of bytes read.
+fn read_up_to<R: Read>(r: &mut R, buf: &mut [u8]) -> Result<usize, DecodingError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match r.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
     }
+    Ok(filled)
 }
 
 fn init_top_macroblocks(width: usize) -> Vec<MacroBlock> {
@@ -1327,3 +1883,152 @@ fn set_chroma_border(
         *top = w;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter_test_decoder(filter_level: u8, sharpness_level: u8) -> Vp8Decoder<&'static [u8]> {
+        let mut decoder = Vp8Decoder::new(&[][..]);
+        decoder.frame.header.filter_level = filter_level;
+        decoder.frame.header.sharpness_level = sharpness_level;
+        decoder
+    }
+
+    fn macroblock(luma_mode: LumaMode, segmentid: u8) -> MacroBlock {
+        MacroBlock {
+            luma_mode,
+            segmentid,
+            ..MacroBlock::default()
+        }
+    }
+
+    #[test]
+    fn bool_decoder() {
+        let mut decoder = BoolDecoder::new(b"hello world");
+        assert!(!decoder.read_flag());
+        assert!(decoder.read_bool(10));
+        assert!(!decoder.read_bool(250));
+        assert_eq!(1, decoder.read_literal(1));
+        assert_eq!(5, decoder.read_literal(3));
+        assert_eq!(64, decoder.read_literal(8));
+        assert_eq!(185, decoder.read_literal(8));
+        assert_eq!(31, decoder.read_literal(8));
+        assert!(!decoder.is_overflow());
+
+        while !decoder.is_overflow() {
+            decoder.read_signed(7);
+        }
+        assert_eq!(decoder.bytes_consumed(), 11);
+    }
+
+    #[test]
+    #[should_panic(expected = "literals have at most 8 bits")]
+    fn bool_decoder_rejects_long_literals() {
+        BoolDecoder::new(b"hello world").read_literal(9);
+    }
+
+    #[test]
+    fn bool_decoder_checkpoint() {
+        let mut decoder = BoolDecoder::new(b"hello world");
+        decoder.read_literal(5);
+        let checkpoint = decoder.checkpoint();
+        let values: Vec<_> = (0..9).map(|_| decoder.read_literal(8)).collect();
+        assert!(!decoder.is_overflow());
+
+        for _ in 0..4 {
+            decoder.read_literal(8);
+        }
+        assert!(decoder.is_overflow());
+
+        decoder.restore(checkpoint);
+        let again: Vec<_> = (0..9).map(|_| decoder.read_literal(8)).collect();
+        assert!(!decoder.is_overflow());
+        assert_eq!(values, again);
+    }
+
+    #[test]
+    fn quantizer_indices_are_clamped() {
+        use crate::vp8_arithmetic_encoder::ArithmeticEncoder;
+
+        // The largest base quantizer index, with the largest positive deltas.
+        let mut encoder = ArithmeticEncoder::new();
+        encoder.write_literal(7, 127);
+        for _ in 0..5 {
+            encoder.write_flag(true);
+            encoder.write_literal(4, 15);
+            encoder.write_flag(false);
+        }
+        let data = encoder.flush_and_get_buffer();
+
+        let mut decoder = Vp8Decoder::new(&[][..]);
+        decoder.b = BoolDecoder::new(&data).decoder;
+        decoder.segments_enabled = true;
+        decoder.segment[0].delta_values = true;
+        decoder.segment[0].quantizer_level = 127;
+        decoder.segment[1].quantizer_level = -127;
+        decoder.read_quantization_indices().unwrap();
+
+        assert_eq!(decoder.frame.header.quantizer_deltas, [15; 5]);
+        let max = &decoder.segment[0];
+        assert_eq!(max.ydc, DC_QUANT[127]);
+        assert_eq!(max.yac, AC_QUANT[127]);
+        assert_eq!(max.y2dc, DC_QUANT[127] * 2);
+        assert_eq!(max.y2ac, (i32::from(AC_QUANT[127]) * 155 / 100) as i16);
+        assert_eq!(max.uvdc, 132);
+        assert_eq!(max.uvac, AC_QUANT[127]);
+        let min = &decoder.segment[1];
+        assert_eq!(min.ydc, DC_QUANT[0]);
+        assert_eq!(min.yac, AC_QUANT[0]);
+        assert_eq!(min.y2dc, DC_QUANT[0] * 2);
+        assert_eq!(min.y2ac, 8);
+        assert_eq!(min.uvdc, DC_QUANT[0]);
+        assert_eq!(min.uvac, AC_QUANT[0]);
+    }
+
+    #[test]
+    fn filter_parameters_with_loop_filter_deltas() {
+        let mut decoder = filter_test_decoder(30, 0);
+        decoder.loop_filter_adjustments_enabled = true;
+        decoder.ref_delta[0] = 10;
+        decoder.mode_delta[0] = -25;
+
+        // The mode delta only applies to macroblocks using B_PRED
+        let mb = macroblock(LumaMode::DC, 0);
+        assert_eq!(decoder.calculate_filter_parameters(&mb), (40, 40, 2));
+        let mb = macroblock(LumaMode::B, 0);
+        assert_eq!(decoder.calculate_filter_parameters(&mb), (15, 15, 1));
+
+        decoder.ref_delta[0] = 63;
+        let mb = macroblock(LumaMode::DC, 0);
+        assert_eq!(decoder.calculate_filter_parameters(&mb).0, 63);
+    }
+
+    #[test]
+    fn filter_parameters_with_segments() {
+        let mut decoder = filter_test_decoder(20, 5);
+        decoder.segments_enabled = true;
+        decoder.segment[1].delta_values = true;
+        decoder.segment[1].loopfilter_level = -8;
+        decoder.segment[2].loopfilter_level = 50;
+
+        // The sharpness limits the interior limit to `9 - sharpness`
+        let mb = macroblock(LumaMode::DC, 0);
+        assert_eq!(decoder.calculate_filter_parameters(&mb), (0, 1, 0));
+        let mb = macroblock(LumaMode::DC, 1);
+        assert_eq!(decoder.calculate_filter_parameters(&mb), (12, 3, 0));
+        let mb = macroblock(LumaMode::DC, 2);
+        assert_eq!(decoder.calculate_filter_parameters(&mb), (50, 4, 2));
+
+        // A frame level of zero disables filtering regardless of the segments
+        decoder.frame.header.filter_level = 0;
+        assert_eq!(decoder.calculate_filter_parameters(&mb), (0, 0, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid tree branch")]
+    fn cyclic_tree_is_rejected() {
+        // The second node branches back to itself.
+        tree_nodes_from([2, 4, -0, 2, -1, -2], [128; 3]);
+    }
+}
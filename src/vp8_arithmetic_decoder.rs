@@ -41,6 +41,7 @@ pub(crate) struct ArithmeticDecoder {
     state: State,
     final_bytes: [u8; 3],
     final_bytes_remaining: i8,
+    len: usize,
 }
 
 #[cfg_attr(test, derive(Debug))]
@@ -52,6 +53,16 @@ struct State {
     bit_count: i32,
 }
 
+/// A saved position of a [`BoolDecoder`](crate::vp8::BoolDecoder) in its partition, see
+/// [`checkpoint`](crate::vp8::BoolDecoder::checkpoint)
+#[cfg_attr(test, derive(Debug))]
+#[derive(Clone, Copy)]
+pub struct Checkpoint {
+    state: State,
+    final_bytes: [u8; 3],
+    final_bytes_remaining: i8,
+}
+
 #[cfg_attr(test, derive(Debug))]
 struct FastDecoder<'a> {
     chunks: &'a [[u8; 4]],
@@ -72,6 +83,7 @@ impl ArithmeticDecoder {
             state,
             final_bytes: [0; 3],
             final_bytes_remaining: Self::FINAL_BYTES_REMAINING_EOF,
+            len: 0,
         }
     }
 
@@ -106,10 +118,54 @@ impl ArithmeticDecoder {
             state,
             final_bytes,
             final_bytes_remaining,
+            len,
         };
         Ok(())
     }
 
+    /// Saves the current position, so that reading can later be resumed from it with
+    /// [`restore`](Self::restore).
+    pub(crate) fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            state: self.state,
+            final_bytes: self.final_bytes,
+            final_bytes_remaining: self.final_bytes_remaining,
+        }
+    }
+
+    /// Returns to a position saved by [`checkpoint`](Self::checkpoint) on this decoder, without
+    /// it having been initialized again in between.
+    pub(crate) fn restore(&mut self, checkpoint: Checkpoint) {
+        self.state = checkpoint.state;
+        self.final_bytes = checkpoint.final_bytes;
+        self.final_bytes_remaining = checkpoint.final_bytes_remaining;
+    }
+
+    /// Returns the length of the buffer the decoder was initialized with.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the number of bytes of the buffer that have been consumed so far. Bytes which have
+    /// been loaded but whose bits were not used yet don't count as consumed.
+    pub(crate) fn bytes_consumed(&self) -> usize {
+        if self.is_past_eof() {
+            return self.len;
+        }
+
+        let final_bytes_len = self.len - 4 * self.chunks.len();
+        let final_bytes_loaded = final_bytes_len - self.final_bytes_remaining.max(0) as usize;
+        let loaded = if self.state.chunk_index < self.chunks.len() {
+            4 * self.state.chunk_index
+        } else {
+            4 * self.chunks.len() + final_bytes_loaded
+        };
+
+        // The bits below the 8 bit window the decoder currently works on are still left to read.
+        let unread_bytes = self.state.bit_count.max(0) as usize / 8;
+        loaded.saturating_sub(unread_bytes).min(self.len)
+    }
+
     /// Start a span of reading operations from the buffer, without stopping
     /// when the buffer runs out. For all valid webp images, the buffer will not
     /// run out prematurely. Conversely if the buffer ends early, the webp image
@@ -261,7 +317,7 @@ impl ArithmeticDecoder {
         }
     }
 
-    fn is_past_eof(&self) -> bool {
+    pub(crate) fn is_past_eof(&self) -> bool {
         self.final_bytes_remaining == Self::FINAL_BYTES_REMAINING_EOF
     }
 
@@ -481,27 +537,39 @@ impl FastDecoder<'_> {
         retval
     }
 
-    fn fast_read_flag(&mut self) -> bool {
-        let State {
-            mut chunk_index,
-            mut value,
-            mut range,
-            mut bit_count,
-        } = self.uncommitted_state;
-
-        if bit_count < 0 {
-            let chunk = self.chunks.get(chunk_index).copied();
+    /// Loads the next chunk if fewer than `bits + 1` bits are left. There is room for it in
+    /// `value` as long as `bits` is below 24.
+    fn fast_refill(&mut self, bits: i32) {
+        debug_assert!(bits < 24);
+        let state = &mut self.uncommitted_state;
+        if state.bit_count < bits {
+            let chunk = self.chunks.get(state.chunk_index).copied();
             // We ignore invalid data inside the `fast_` functions,
             // but we increase `chunk_index` below, so we can check
             // whether we read invalid data in `commit_if_valid`.
             let chunk = chunk.unwrap_or_default();
 
             let v = u32::from_be_bytes(chunk);
-            chunk_index += 1;
-            value <<= 32;
-            value |= u64::from(v);
-            bit_count += 32;
+            state.chunk_index += 1;
+            state.value <<= 32;
+            state.value |= u64::from(v);
+            state.bit_count += 32;
         }
+    }
+
+    fn fast_read_flag(&mut self) -> bool {
+        self.fast_refill(0);
+        self.fast_read_flag_without_refill()
+    }
+
+    /// Reads a flag, which needs at least one bit to be left.
+    fn fast_read_flag_without_refill(&mut self) -> bool {
+        let State {
+            chunk_index,
+            mut value,
+            mut range,
+            mut bit_count,
+        } = self.uncommitted_state;
         debug_assert!(bit_count >= 0);
 
         let half_range = range / 2;
@@ -538,9 +606,12 @@ impl FastDecoder<'_> {
     }
 
     fn fast_read_literal(&mut self, n: u8) -> u8 {
+        // A flag leaves at least half of the range, so each flag consumes at most one bit and a
+        // single refill covers all of them.
+        self.fast_refill(i32::from(n) - 1);
         let mut v = 0u8;
         for _ in 0..n {
-            let b = self.fast_read_flag();
+            let b = self.fast_read_flag_without_refill();
             v = (v << 1) + u8::from(b);
         }
         v
@@ -561,6 +632,7 @@ impl FastDecoder<'_> {
 
 #[cfg(test)]
 mod tests {
+    #![allow(clippy::bool_assert_comparison, clippy::manual_div_ceil)]
     use super::*;
 
     #[test]
@@ -602,6 +674,30 @@ mod tests {
         decoder.check(res, ()).unwrap();
     }
 
+    #[test]
+    fn test_arithmetic_decoder_literal_matches_flags() {
+        let data: Vec<u8> = (0..64u32).map(|i| (i * 167 + 13) as u8).collect();
+        let decoder = || {
+            let mut decoder = ArithmeticDecoder::new();
+            let mut buf = vec![[0u8; 4]; data.len().div_ceil(4)];
+            buf.as_mut_slice().as_flattened_mut()[..data.len()].copy_from_slice(&data);
+            decoder.init(buf, data.len()).unwrap();
+            decoder
+        };
+
+        let (mut literals, mut flags) = (decoder(), decoder());
+        let mut res = literals.start_accumulated_result();
+        for n in (1..=8).cycle().take(100) {
+            let literal = literals.read_literal(n).or_accumulate(&mut res);
+            let mut v = 0;
+            for _ in 0..n {
+                v = (v << 1) + u8::from(flags.read_flag().or_accumulate(&mut res));
+            }
+            assert_eq!(literal, v);
+        }
+        literals.check(res, ()).unwrap();
+    }
+
     #[test]
     fn test_arithmetic_decoder_uninit() {
         let mut decoder = ArithmeticDecoder::new();
@@ -610,4 +706,60 @@ mod tests {
         let result = decoder.check(res, ());
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_arithmetic_decoder_bytes_consumed() {
+        let mut decoder = ArithmeticDecoder::new();
+        let data = b"hello world";
+        let size = data.len();
+        let mut buf = vec![[0u8; 4]; size.div_ceil(4)];
+        buf.as_mut_slice().as_flattened_mut()[..size].copy_from_slice(&data[..]);
+        decoder.init(buf, size).unwrap();
+        assert_eq!(decoder.len(), 11);
+        assert_eq!(decoder.bytes_consumed(), 0);
+
+        let mut res = decoder.start_accumulated_result();
+        let mut consumed = Vec::new();
+        for _ in 0..10 {
+            decoder.read_literal(8).or_accumulate(&mut res);
+            consumed.push(decoder.bytes_consumed());
+        }
+        decoder.check(res, ()).unwrap();
+        // Each literal uses one byte with the even probabilities of `read_literal`, on top of the
+        // byte loaded into the window at the start
+        assert_eq!(consumed, (2..=11).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_arithmetic_decoder_checkpoint() {
+        let mut decoder = ArithmeticDecoder::new();
+        let data = b"hello world";
+        let size = data.len();
+        let mut buf = vec![[0u8; 4]; size.div_ceil(4)];
+        buf.as_mut_slice().as_flattened_mut()[..size].copy_from_slice(&data[..]);
+        decoder.init(buf, size).unwrap();
+
+        let mut res = decoder.start_accumulated_result();
+        decoder.read_literal(5).or_accumulate(&mut res);
+        let checkpoint = decoder.checkpoint();
+        let values: Vec<_> = (0..9)
+            .map(|_| decoder.read_literal(8).or_accumulate(&mut res))
+            .collect();
+        decoder.check(res, ()).unwrap();
+
+        // Reading past the end and then going back recovers from the error
+        let mut res = decoder.start_accumulated_result();
+        for _ in 0..4 {
+            decoder.read_literal(8).or_accumulate(&mut res);
+        }
+        assert!(decoder.check(res, ()).is_err());
+
+        decoder.restore(checkpoint);
+        let mut res = decoder.start_accumulated_result();
+        let again: Vec<_> = (0..9)
+            .map(|_| decoder.read_literal(8).or_accumulate(&mut res))
+            .collect();
+        decoder.check(res, ()).unwrap();
+        assert_eq!(values, again);
+    }
 }
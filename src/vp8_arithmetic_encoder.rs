@@ -163,6 +163,7 @@ impl ArithmeticEncoder {
 
 #[cfg(test)]
 mod tests {
+    #![allow(clippy::bool_assert_comparison, clippy::manual_div_ceil)]
     use crate::vp8_arithmetic_decoder::ArithmeticDecoder;
     use crate::vp8_common::*;
 
@@ -38,10 +38,10 @@ pub(crate) const B_VL_PRED: i8 = 7;
 pub(crate) const B_HD_PRED: i8 = 8;
 pub(crate) const B_HU_PRED: i8 = 9;
 
-// Prediction mode enum
+/// Prediction mode of the luma plane of a macroblock
 #[repr(i8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
-pub(crate) enum LumaMode {
+pub enum LumaMode {
     /// Predict DC using row above and column to the left.
     #[default]
     DC = DC_PRED,
@@ -82,9 +82,10 @@ impl LumaMode {
     }
 }
 
+/// Prediction mode of the chroma planes of a macroblock
 #[repr(i8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
-pub(crate) enum ChromaMode {
+pub enum ChromaMode {
     /// Predict DC using row above and column to the left.
     #[default]
     DC = DC_PRED,
@@ -111,19 +112,39 @@ impl ChromaMode {
     }
 }
 
+/// Prediction mode of a 4x4 luma subblock of a macroblock with [`LumaMode::B`]
 #[repr(i8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
-pub(crate) enum IntraMode {
+pub enum IntraMode {
+    /// Predict DC using row above and column to the left.
     #[default]
     DC = B_DC_PRED,
+
+    /// Propagate second differences.
     TM = B_TM_PRED,
+
+    /// Predict rows using the smoothed row above.
     VE = B_VE_PRED,
+
+    /// Predict columns using the smoothed column to the left.
     HE = B_HE_PRED,
+
+    /// Predict along the diagonal down and to the left.
     LD = B_LD_PRED,
+
+    /// Predict along the diagonal down and to the right.
     RD = B_RD_PRED,
+
+    /// Predict along a direction between vertical and down to the right.
     VR = B_VR_PRED,
+
+    /// Predict along a direction between vertical and down to the left.
     VL = B_VL_PRED,
+
+    /// Predict along a direction between horizontal and down to the right.
     HD = B_HD_PRED,
+
+    /// Predict along a direction between horizontal and up to the right.
     HU = B_HU_PRED,
 }
 
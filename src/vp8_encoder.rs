@@ -3,7 +3,7 @@ use std::io::Write;
 use byteorder_lite::{LittleEndian, WriteBytesExt};
 
 use crate::transform;
-use crate::vp8::Frame;
+use crate::vp8::{Frame, FrameHeader};
 use crate::vp8_arithmetic_encoder::ArithmeticEncoder;
 use crate::vp8_common::*;
 use crate::vp8_prediction::*;
@@ -148,7 +148,7 @@ impl<W: Write> Vp8Encoder<W> {
         &mut self,
         partition_size: u32,
     ) -> Result<(), EncodingError> {
-        let version = u32::from(self.frame.version);
+        let version = u32::from(self.frame.header.version);
         let for_display = if self.frame.for_display { 1 } else { 0 };
 
         let keyframe_bit = 0;
@@ -177,9 +177,11 @@ impl<W: Write> Vp8Encoder<W> {
             self.encode_segment_updates();
         }
 
-        self.encoder.write_flag(self.frame.filter_type);
-        self.encoder.write_literal(6, self.frame.filter_level);
-        self.encoder.write_literal(3, self.frame.sharpness_level);
+        self.encoder.write_flag(self.frame.header.simple_filter);
+        self.encoder
+            .write_literal(6, self.frame.header.filter_level);
+        self.encoder
+            .write_literal(3, self.frame.header.sharpness_level);
 
         self.encoder.write_flag(self.loop_filter_adjustments);
         if self.loop_filter_adjustments {
@@ -733,14 +735,24 @@ impl<W: Write> Vp8Encoder<W> {
             ubuf: u_buf,
             vbuf: v_buf,
 
-            version: 0,
-
             for_display: true,
             pixel_type: 0,
 
-            filter_type: false,
-            filter_level: 63,
-            sharpness_level: 7,
+            header: FrameHeader {
+                version: 0,
+                for_display: true,
+                simple_filter: false,
+                filter_level: 63,
+                sharpness_level: 7,
+                ..FrameHeader::default()
+            },
+            macroblock_info: Vec::new(),
+            macroblock_quant: Vec::new(),
+            partition_usage: Vec::new(),
+            partition_sizes: Vec::new(),
+            incomplete: false,
+            loop_filtered: false,
+            macroblock_coefficients: None,
         };
 
         self.top_complexity = vec![Complexity::default(); usize::from(mb_width)];
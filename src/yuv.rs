@@ -25,6 +25,48 @@
 //! It interpolates u and v so that for e.g. the pixel 1 down and 1 from the left the u value
 //! would be (9*u0 + 3*u1 + 3*u2 + u3 + 8) / 16 and similar for the other pixels
 //! The edges are mirrored, so for the pixel 1 down and 0 from the left it uses (9*u0 + 3*u2 + 3*u0 + u2 + 8) / 16
+//!
+//! The conversion only uses integer arithmetic, so the output is identical on all platforms.
+
+use std::ops::Range;
+
+/// The layout of the pixels of an output row of the conversion
+pub(crate) trait PixelLayout {
+    /// The type of the elements of an output row
+    type Element;
+    /// The number of elements each pixel takes
+    const LEN: usize;
+    /// Stores the color of a pixel in its elements
+    fn set(pixel: &mut [Self::Element], r: u8, g: u8, b: u8);
+}
+
+/// Pixels of `BPP` bytes starting with red, green and blue. A fourth byte is left untouched.
+pub(crate) struct Bytes<const BPP: usize>;
+
+impl<const BPP: usize> PixelLayout for Bytes<BPP> {
+    type Element = u8;
+    const LEN: usize = BPP;
+
+    #[inline(always)]
+    fn set(pixel: &mut [u8], r: u8, g: u8, b: u8) {
+        pixel[0] = r;
+        pixel[1] = g;
+        pixel[2] = b;
+    }
+}
+
+/// Opaque pixels packed into a `u32` as `0xAARRGGBB`
+pub(crate) struct Argb;
+
+impl PixelLayout for Argb {
+    type Element = u32;
+    const LEN: usize = 1;
+
+    #[inline(always)]
+    fn set(pixel: &mut [u32], r: u8, g: u8, b: u8) {
+        pixel[0] = u32::from_be_bytes([0xff, r, g, b]);
+    }
+}
 
 /// `_mm_mulhi_epu16` emulation
 fn mulhi(v: u8, coeff: u16) -> i32 {
@@ -91,7 +133,7 @@ pub(crate) fn fill_rgb_buffer_fancy<const BPP: usize>(
     let top_row_u = &u_buffer[..chroma_width];
     let top_row_v = &v_buffer[..chroma_width];
     let top_row_buffer = &mut buffer[..width * BPP];
-    fill_row_fancy_with_1_uv_row::<BPP>(top_row_buffer, top_row_y, top_row_u, top_row_v);
+    fill_row_fancy_with_1_uv_row::<Bytes<BPP>>(top_row_buffer, top_row_y, top_row_u, top_row_v);
 
     let mut main_row_chunks = buffer[width * BPP..].chunks_exact_mut(width * BPP * 2);
     // the y buffer iterator limits the end of the row iterator so we need this end index
@@ -113,7 +155,7 @@ pub(crate) fn fill_rgb_buffer_fancy<const BPP: usize>(
         let (v_row_1, v_row_2) = v_rows.split_at(chroma_buffer_width);
         let (row_buf_1, row_buf_2) = row_buffer.split_at_mut(width * BPP);
         let (y_row_1, y_row_2) = y_rows.split_at(buffer_width);
-        fill_row_fancy_with_2_uv_rows::<BPP>(
+        fill_row_fancy_with_2_uv_rows::<Bytes<BPP>>(
             row_buf_1,
             &y_row_1[..width],
             &u_row_1[..chroma_width],
@@ -121,7 +163,7 @@ pub(crate) fn fill_rgb_buffer_fancy<const BPP: usize>(
             &v_row_1[..chroma_width],
             &v_row_2[..chroma_width],
         );
-        fill_row_fancy_with_2_uv_rows::<BPP>(
+        fill_row_fancy_with_2_uv_rows::<Bytes<BPP>>(
             row_buf_2,
             &y_row_2[..width],
             &u_row_2[..chroma_width],
@@ -142,7 +184,7 @@ pub(crate) fn fill_rgb_buffer_fancy<const BPP: usize>(
 
         let final_u_row = &u_buffer[start_chroma_index..];
         let final_v_row = &v_buffer[start_chroma_index..];
-        fill_row_fancy_with_1_uv_row::<BPP>(
+        fill_row_fancy_with_1_uv_row::<Bytes<BPP>>(
             final_row_buffer,
             &final_y_row[..width],
             &final_u_row[..chroma_width],
@@ -151,9 +193,52 @@ pub(crate) fn fill_rgb_buffer_fancy<const BPP: usize>(
     }
 }
 
+/// Fills the pixels `columns` of the single output row `y` like [`fill_rgb_buffer_fancy`] does, so
+/// that an image can be converted as its rows become available
+///
+/// `columns` must start at an even column. The first pixel converted, and the last one if an even
+/// number of them is converted, are interpolated like those at the edges of the image, so they
+/// only match the full row at its ends.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn fill_rgb_row_fancy<P: PixelLayout>(
+    row_buffer: &mut [P::Element],
+    y: usize,
+    columns: Range<usize>,
+    y_buffer: &[u8],
+    u_buffer: &[u8],
+    v_buffer: &[u8],
+    width: usize,
+    height: usize,
+    buffer_width: usize,
+) {
+    debug_assert!(columns.start % 2 == 0);
+    let chroma_buffer_width = buffer_width / 2;
+    let chroma_width = width.div_ceil(2);
+    let y_row = &y_buffer[y * buffer_width..][..width][columns.clone()];
+    let chroma_columns = columns.start / 2..chroma_width;
+    let u_row = |c: usize| &u_buffer[c * chroma_buffer_width..][chroma_columns.clone()];
+    let v_row = |c: usize| &v_buffer[c * chroma_buffer_width..][chroma_columns.clone()];
+
+    // the first row, and the last one for even heights, only have a single u/v row next to them
+    let main = y / 2;
+    if y == 0 || (y == height - 1 && height % 2 == 0) {
+        fill_row_fancy_with_1_uv_row::<P>(row_buffer, y_row, u_row(main), v_row(main));
+    } else {
+        let secondary = if y % 2 == 1 { main + 1 } else { main - 1 };
+        fill_row_fancy_with_2_uv_rows::<P>(
+            row_buffer,
+            y_row,
+            u_row(main),
+            u_row(secondary),
+            v_row(main),
+            v_row(secondary),
+        );
+    }
+}
+
 /// Fills a row with the fancy interpolation as detailed
-fn fill_row_fancy_with_2_uv_rows<const BPP: usize>(
-    row_buffer: &mut [u8],
+fn fill_row_fancy_with_2_uv_rows<P: PixelLayout>(
+    row_buffer: &mut [P::Element],
     y_row: &[u8],
     u_row_1: &[u8],
     u_row_2: &[u8],
@@ -162,19 +247,19 @@ fn fill_row_fancy_with_2_uv_rows<const BPP: usize>(
 ) {
     // need to do left pixel separately since it will only have one u/v value
     {
-        let rgb1 = &mut row_buffer[0..3];
+        let rgb1 = &mut row_buffer[..P::LEN];
         let y_value = y_row[0];
         // first pixel uses the first u/v as the main one
         let u_value = get_fancy_chroma_value(u_row_1[0], u_row_1[0], u_row_2[0], u_row_2[0]);
         let v_value = get_fancy_chroma_value(v_row_1[0], v_row_1[0], v_row_2[0], v_row_2[0]);
-        set_pixel(rgb1, y_value, u_value, v_value);
+        set_pixel::<P>(rgb1, y_value, u_value, v_value);
     }
 
-    let rest_row_buffer = &mut row_buffer[BPP..];
+    let rest_row_buffer = &mut row_buffer[P::LEN..];
     let rest_y_row = &y_row[1..];
 
     // we do two pixels at a time since they share the same u/v values
-    let mut main_row_chunks = rest_row_buffer.chunks_exact_mut(BPP * 2);
+    let mut main_row_chunks = rest_row_buffer.chunks_exact_mut(P::LEN * 2);
     let mut main_y_chunks = rest_y_row.chunks_exact(2);
 
     for (((((rgb, y_val), u_val_1), u_val_2), v_val_1), v_val_2) in (&mut main_row_chunks)
@@ -185,19 +270,19 @@ fn fill_row_fancy_with_2_uv_rows<const BPP: usize>(
         .zip(v_row_2.windows(2))
     {
         {
-            let rgb1 = &mut rgb[0..3];
+            let rgb1 = &mut rgb[..P::LEN];
             let y_value = y_val[0];
             // first pixel uses the first u/v as the main one
             let u_value = get_fancy_chroma_value(u_val_1[0], u_val_1[1], u_val_2[0], u_val_2[1]);
             let v_value = get_fancy_chroma_value(v_val_1[0], v_val_1[1], v_val_2[0], v_val_2[1]);
-            set_pixel(rgb1, y_value, u_value, v_value);
+            set_pixel::<P>(rgb1, y_value, u_value, v_value);
         }
         {
-            let rgb2 = &mut rgb[BPP..];
+            let rgb2 = &mut rgb[P::LEN..];
             let y_value = y_val[1];
             let u_value = get_fancy_chroma_value(u_val_1[1], u_val_1[0], u_val_2[1], u_val_2[0]);
             let v_value = get_fancy_chroma_value(v_val_1[1], v_val_1[0], v_val_2[1], v_val_2[0]);
-            set_pixel(rgb2, y_value, u_value, v_value);
+            set_pixel::<P>(rgb2, y_value, u_value, v_value);
         }
     }
 
@@ -211,32 +296,32 @@ fn fill_row_fancy_with_2_uv_rows<const BPP: usize>(
         let final_v_1 = *v_row_1.last().unwrap();
         let final_v_2 = *v_row_2.last().unwrap();
 
-        let rgb1 = &mut rgb[0..3];
+        let rgb1 = &mut rgb[..P::LEN];
         // first pixel uses the first u/v as the main one
         let u_value = get_fancy_chroma_value(final_u_1, final_u_1, final_u_2, final_u_2);
         let v_value = get_fancy_chroma_value(final_v_1, final_v_1, final_v_2, final_v_2);
-        set_pixel(rgb1, *y_value, u_value, v_value);
+        set_pixel::<P>(rgb1, *y_value, u_value, v_value);
     }
 }
 
-fn fill_row_fancy_with_1_uv_row<const BPP: usize>(
-    row_buffer: &mut [u8],
+fn fill_row_fancy_with_1_uv_row<P: PixelLayout>(
+    row_buffer: &mut [P::Element],
     y_row: &[u8],
     u_row: &[u8],
     v_row: &[u8],
 ) {
     // doing left pixel first
     {
-        let rgb1 = &mut row_buffer[0..3];
+        let rgb1 = &mut row_buffer[..P::LEN];
         let y_value = y_row[0];
 
         let u_value = u_row[0];
         let v_value = v_row[0];
-        set_pixel(rgb1, y_value, u_value, v_value);
+        set_pixel::<P>(rgb1, y_value, u_value, v_value);
     }
 
     // two pixels at a time since they share the same u/v value
-    let mut main_row_chunks = row_buffer[BPP..].chunks_exact_mut(BPP * 2);
+    let mut main_row_chunks = row_buffer[P::LEN..].chunks_exact_mut(P::LEN * 2);
     let mut main_y_row_chunks = y_row[1..].chunks_exact(2);
 
     for (((rgb, y_val), u_val), v_val) in (&mut main_row_chunks)
@@ -245,19 +330,19 @@ fn fill_row_fancy_with_1_uv_row<const BPP: usize>(
         .zip(v_row.windows(2))
     {
         {
-            let rgb1 = &mut rgb[0..3];
+            let rgb1 = &mut rgb[..P::LEN];
             let y_value = y_val[0];
             // first pixel uses the first u/v as the main one
             let u_value = get_fancy_chroma_value(u_val[0], u_val[1], u_val[0], u_val[1]);
             let v_value = get_fancy_chroma_value(v_val[0], v_val[1], v_val[0], v_val[1]);
-            set_pixel(rgb1, y_value, u_value, v_value);
+            set_pixel::<P>(rgb1, y_value, u_value, v_value);
         }
         {
-            let rgb2 = &mut rgb[BPP..];
+            let rgb2 = &mut rgb[P::LEN..];
             let y_value = y_val[1];
             let u_value = get_fancy_chroma_value(u_val[1], u_val[0], u_val[1], u_val[0]);
             let v_value = get_fancy_chroma_value(v_val[1], v_val[0], v_val[1], v_val[0]);
-            set_pixel(rgb2, y_value, u_value, v_value);
+            set_pixel::<P>(rgb2, y_value, u_value, v_value);
         }
     }
 
@@ -268,7 +353,7 @@ fn fill_row_fancy_with_1_uv_row<const BPP: usize>(
         let final_u = *u_row.last().unwrap();
         let final_v = *v_row.last().unwrap();
 
-        set_pixel(rgb, *final_y, final_u, final_v);
+        set_pixel::<P>(rgb, *final_y, final_u, final_v);
     }
 }
 
@@ -282,10 +367,8 @@ fn get_fancy_chroma_value(main: u8, secondary1: u8, secondary2: u8, tertiary: u8
 }
 
 #[inline]
-fn set_pixel(rgb: &mut [u8], y: u8, u: u8, v: u8) {
-    rgb[0] = yuv_to_r(y, v);
-    rgb[1] = yuv_to_g(y, u, v);
-    rgb[2] = yuv_to_b(y, u);
+fn set_pixel<P: PixelLayout>(rgb: &mut [P::Element], y: u8, u: u8, v: u8) {
+    P::set(rgb, yuv_to_r(y, v), yuv_to_g(y, u, v), yuv_to_b(y, u));
 }
 
 /// Simple conversion, not currently used but could add a config to allow for using the simple
@@ -312,7 +395,7 @@ pub(crate) fn fill_rgb_buffer_simple<const BPP: usize>(
         .zip(u_row_twice_iter)
         .zip(v_row_twice_iter)
     {
-        fill_rgba_row_simple::<BPP>(
+        fill_rgba_row_simple::<Bytes<BPP>>(
             &y_row[..width],
             &u_row[..chroma_width],
             &v_row[..chroma_width],
@@ -321,14 +404,38 @@ pub(crate) fn fill_rgb_buffer_simple<const BPP: usize>(
     }
 }
 
-fn fill_rgba_row_simple<const BPP: usize>(
+/// Fills the pixels `columns` of the single output row `y` like [`fill_rgb_buffer_simple`] does.
+/// `columns` must start at an even column.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn fill_rgb_row_simple<P: PixelLayout>(
+    row_buffer: &mut [P::Element],
+    y: usize,
+    columns: Range<usize>,
+    y_buffer: &[u8],
+    u_buffer: &[u8],
+    v_buffer: &[u8],
+    width: usize,
+    chroma_width: usize,
+    buffer_width: usize,
+) {
+    debug_assert!(columns.start % 2 == 0);
+    let chroma_buffer_width = buffer_width / 2;
+    fill_rgba_row_simple::<P>(
+        &y_buffer[y * buffer_width..][..width][columns.clone()],
+        &u_buffer[y / 2 * chroma_buffer_width..][..chroma_width][columns.start / 2..],
+        &v_buffer[y / 2 * chroma_buffer_width..][..chroma_width][columns.start / 2..],
+        row_buffer,
+    );
+}
+
+fn fill_rgba_row_simple<P: PixelLayout>(
     y_vec: &[u8],
     u_vec: &[u8],
     v_vec: &[u8],
-    rgba: &mut [u8],
+    rgba: &mut [P::Element],
 ) {
     // Fill 2 pixels per iteration: these pixels share `u` and `v` components
-    let mut rgb_chunks = rgba.chunks_exact_mut(BPP * 2);
+    let mut rgb_chunks = rgba.chunks_exact_mut(P::LEN * 2);
     let mut y_chunks = y_vec.chunks_exact(2);
     let mut u_iter = u_vec.iter();
     let mut v_iter = v_vec.iter();
@@ -349,19 +456,13 @@ fn fill_rgba_row_simple<const BPP: usize>(
         let get_g = |y: u8| clip(mulhi(y, 19077) - coeffs[1] - coeffs[2] + 8708);
         let get_b = |y: u8| clip(mulhi(y, 19077) + coeffs[3] - 17685);
 
-        let rgb1 = &mut rgb[0..3];
-        rgb1[0] = get_r(y[0]);
-        rgb1[1] = get_g(y[0]);
-        rgb1[2] = get_b(y[0]);
-
-        let rgb2 = &mut rgb[BPP..];
-        rgb2[0] = get_r(y[1]);
-        rgb2[1] = get_g(y[1]);
-        rgb2[2] = get_b(y[1]);
+        let (rgb1, rgb2) = rgb.split_at_mut(P::LEN);
+        P::set(rgb1, get_r(y[0]), get_g(y[0]), get_b(y[0]));
+        P::set(rgb2, get_r(y[1]), get_g(y[1]), get_b(y[1]));
     }
 
     let remainder = rgb_chunks.into_remainder();
-    if remainder.len() >= 3 {
+    if remainder.len() >= P::LEN {
         if let (Some(&y), Some(&u), Some(&v)) = (
             y_chunks.remainder().iter().next(),
             u_iter.next(),
@@ -374,9 +475,12 @@ fn fill_rgba_row_simple<const BPP: usize>(
                 mulhi(u, 33050),
             ];
 
-            remainder[0] = clip(mulhi(y, 19077) + coeffs[0] - 14234);
-            remainder[1] = clip(mulhi(y, 19077) - coeffs[1] - coeffs[2] + 8708);
-            remainder[2] = clip(mulhi(y, 19077) + coeffs[3] - 17685);
+            P::set(
+                remainder,
+                clip(mulhi(y, 19077) + coeffs[0] - 14234),
+                clip(mulhi(y, 19077) - coeffs[1] - coeffs[2] + 8708),
+                clip(mulhi(y, 19077) + coeffs[3] - 17685),
+            );
         }
     }
 }
@@ -475,7 +579,7 @@ pub(crate) fn convert_image_y<const BPP: usize>(
 // this is converted to 16 bit fixed point by multiplying by 2^16
 // and shifting back
 
-fn rgb_to_y(rgb: &[u8]) -> u8 {
+pub(crate) fn rgb_to_y(rgb: &[u8]) -> u8 {
     let luma = 16839 * i32::from(rgb[0]) + 33059 * i32::from(rgb[1]) + 6420 * i32::from(rgb[2]);
     ((luma + YUV_HALF + (16 << YUV_FIX)) >> YUV_FIX) as u8
 }
@@ -579,4 +683,34 @@ mod tests {
         assert_eq!(yuv_to_g(y, u, v), 255);
         assert_eq!(yuv_to_b(y, u), 40);
     }
+
+    #[test]
+    fn test_yuv_conversions_limited_range() {
+        // black and white of the limited range map to the ends of the full range
+        assert_eq!(
+            [yuv_to_r(16, 128), yuv_to_g(16, 128, 128), yuv_to_b(16, 128)],
+            [0, 0, 0]
+        );
+        assert_eq!(
+            [
+                yuv_to_r(235, 128),
+                yuv_to_g(235, 128, 128),
+                yuv_to_b(235, 128)
+            ],
+            [255, 255, 255]
+        );
+
+        // Pins the output for a grid of inputs, it must not change across platforms
+        let mut sum = [0u64; 3];
+        for y in 0..=255 {
+            for u in (0..=255).step_by(3) {
+                for v in (0..=255).step_by(5) {
+                    sum[0] += u64::from(yuv_to_r(y, v));
+                    sum[1] += u64::from(yuv_to_g(y, u, v));
+                    sum[2] += u64::from(yuv_to_b(y, u));
+                }
+            }
+        }
+        assert_eq!(sum, [146978730, 148409669, 146701672]);
+    }
 }
@@ -50,6 +50,15 @@ fn reference_test_with_options(
     let mut decoder =
         image_webp::WebPDecoder::new_with_options(Cursor::new(contents), options).unwrap();
     let (width, height) = decoder.dimensions();
+    assert_eq!(decoder.animation_params().is_some(), decoder.is_animated());
+    let bytes_per_pixel = match decoder.color_type() {
+        image_webp::WebPColorType::Rgb8 => 3,
+        image_webp::WebPColorType::Rgba8 => 4,
+    };
+    assert_eq!(
+        decoder.output_buffer_size(),
+        Some(width as usize * height as usize * bytes_per_pixel)
+    );
 
     // Decode reference PNG
     let reference_file = custom_reference_file.unwrap_or(file);
@@ -187,17 +196,1492 @@ macro_rules! reftest_nofancy {
     }
 }
 
+#[test]
+fn reftest_nofilter_regression_lossy_sharpness() {
+    let mut options = image_webp::WebPDecodeOptions::default();
+    options.loop_filter = false;
+    reference_test_with_options(
+        "regression/lossy_sharpness",
+        options,
+        Some("regression_nofilter/lossy_sharpness"),
+    );
+}
+
+#[test]
+fn reftest_dither_regression_lossy_dither() {
+    let mut options = image_webp::WebPDecodeOptions::default();
+    options.dithering_strength = 100;
+    reference_test_with_options(
+        "regression/lossy_dither",
+        options,
+        Some("regression_dither/lossy_dither"),
+    );
+}
+
 reftest!(gallery1, 1, 2, 3, 4, 5);
 reftest_nofancy!(gallery1, 1, 2, 3, 4, 5);
+reftest_nofancy!(
+    regression,
+    alpha_filter_horizontal,
+    lossy_segments,
+    lossy_sharpness,
+    lossy_17x17,
+    lossy_33x17,
+    lossy_100x63,
+    lossy_alpha_33x17,
+    lossy_saturated
+);
 reftest!(gallery2, 1_webp_ll, 2_webp_ll, 3_webp_ll, 4_webp_ll, 5_webp_ll);
 reftest!(gallery2, 1_webp_a, 2_webp_a, 3_webp_a, 4_webp_a, 5_webp_a);
 reftest!(animated, random_lossless, random_lossy);
 reftest!(
     regression,
+    alpha_uncompressed,
+    alpha_filter_horizontal,
     color_index,
     dark,
     tiny,
     lossless_indexed_1bit_palette,
     lossless_indexed_2bit_palette,
-    lossless_indexed_4bit_palette
+    lossless_indexed_4bit_palette,
+    lossy_8_partitions,
+    lossy_segments,
+    lossy_sharpness,
+    lossy_simple_filter,
+    lossy_17x17,
+    lossy_33x17,
+    lossy_100x63,
+    lossy_alpha_33x17,
+    lossy_skipped_macroblocks,
+    lossy_edge_modes_1,
+    lossy_edge_modes_2,
+    lossy_edge_modes_3,
+    lossy_edge_modes_4,
+    lossy_edge_modes_5,
+    lossy_edge_modes_6,
+    lossy_saturated
 );
+
+#[test]
+fn container_format() {
+    for (file, format, lossy) in [
+        ("gallery1/1", image_webp::WebPFormat::Lossy, true),
+        (
+            "gallery2/1_webp_ll",
+            image_webp::WebPFormat::Lossless,
+            false,
+        ),
+        ("gallery2/1_webp_a", image_webp::WebPFormat::Extended, true),
+        (
+            "animated/random_lossy",
+            image_webp::WebPFormat::Extended,
+            true,
+        ),
+    ] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        assert_eq!(decoder.format(), format, "{file}");
+        assert_eq!(decoder.is_lossy(), lossy, "{file}");
+    }
+}
+
+#[test]
+fn max_dimensions() {
+    for file in [
+        "gallery1/1",
+        "gallery2/1_webp_ll",
+        "gallery2/1_webp_a",
+        "animated/random_lossy",
+    ] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let (width, height) = image_webp::WebPDecoder::new(Cursor::new(&contents))
+            .unwrap()
+            .dimensions();
+
+        for (max_dimensions, accepted) in [
+            ((width, height), true),
+            ((width - 1, height), false),
+            ((width, height - 1), false),
+        ] {
+            let mut options = image_webp::WebPDecodeOptions::default();
+            options.max_dimensions = max_dimensions;
+            let result = image_webp::WebPDecoder::new_with_options(Cursor::new(&contents), options);
+            match result {
+                Ok(_) => assert!(accepted, "{file} exceeding {max_dimensions:?} was accepted"),
+                Err(e) => {
+                    assert!(!accepted, "{file}: {e}");
+                    assert!(matches!(e, image_webp::DecodingError::ImageTooLarge));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn animation_frame_iteration() {
+    let contents = std::fs::read("tests/images/animated/random_lossless.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    assert!(decoder.is_animated());
+
+    let mut first_frame = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.read_frame(&mut first_frame).unwrap();
+
+    let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+    for _ in 1..decoder.num_frames() {
+        decoder.read_frame(&mut data).unwrap();
+    }
+
+    // Once exhausted, the decoder keeps reporting the end and leaves the buffer alone.
+    let last_frame = data.clone();
+    assert!(matches!(
+        decoder.read_frame(&mut data),
+        Err(image_webp::DecodingError::NoMoreFrames)
+    ));
+    assert_eq!(data, last_frame);
+
+    decoder.reset_animation();
+    decoder.read_frame(&mut data).unwrap();
+    assert_eq!(data, first_frame);
+}
+
+#[test]
+fn every_output_byte_is_written() {
+    // Decoding into buffers with different initial contents gives the same result only if every
+    // byte is overwritten, including those of partial macroblocks at the right and bottom.
+    for directory in ["animated", "gallery1", "gallery2", "regression"] {
+        for entry in std::fs::read_dir(format!("tests/images/{directory}")).unwrap() {
+            let path = entry.unwrap().path();
+            let contents = std::fs::read(&path).unwrap();
+            let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+            let size = decoder.output_buffer_size().unwrap();
+
+            let mut zeros = vec![0; size];
+            decoder.read_image(&mut zeros).unwrap();
+            let mut ones = vec![0xff; size];
+            decoder.read_image(&mut ones).unwrap();
+            assert!(zeros == ones, "{}", path.display());
+
+            if decoder.is_animated() {
+                for i in 0..decoder.num_frames() {
+                    zeros.fill(0);
+                    ones.fill(0xff);
+                    decoder.seek_frame(i).unwrap();
+                    decoder.read_frame(&mut zeros).unwrap();
+                    decoder.seek_frame(i).unwrap();
+                    decoder.read_frame(&mut ones).unwrap();
+                    assert!(zeros == ones, "{} frame {i}", path.display());
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn read_image_gray_alpha() {
+    for file in [
+        "regression/lossy_alpha_33x17",
+        "regression/lossy_100x63",
+        "gallery2/1_webp_a",
+        "gallery2/1_webp_ll",
+        "animated/random_lossy",
+    ] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        let mut gray_alpha = vec![0; decoder.output_buffer_size_gray_alpha().unwrap()];
+        decoder.read_image_gray_alpha(&mut gray_alpha).unwrap();
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut data).unwrap();
+
+        // Converting the luma and chroma to RGB and back only loses the luma to rounding and
+        // clipping, so it stays close to the luma of the bitstream.
+        let bytes_per_pixel = if decoder.has_alpha() { 4 } else { 3 };
+        let mut error = 0;
+        for (gray_alpha, pixel) in gray_alpha
+            .chunks_exact(2)
+            .zip(data.chunks_exact(bytes_per_pixel))
+        {
+            let [r, g, b] = [0, 1, 2].map(|i| i32::from(pixel[i]));
+            let luma = (16839 * r + 33059 * g + 6420 * b + (1 << 15) + (16 << 16)) >> 16;
+            error += (luma - i32::from(gray_alpha[0])).abs();
+            assert_eq!(
+                gray_alpha[1],
+                pixel.get(3).copied().unwrap_or(255),
+                "{file}"
+            );
+        }
+        assert!(error * 10 < gray_alpha.len() as i32 / 2, "{file}: {error}");
+    }
+
+    let contents = std::fs::read("tests/images/regression/tiny.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    assert!(matches!(
+        decoder.read_image_gray_alpha(&mut [0; 1]),
+        Err(image_webp::DecodingError::ImageTooLarge)
+    ));
+}
+
+#[test]
+fn read_yuv() {
+    fn assert_send<T: Send>(_: &T) {}
+
+    for file in ["regression/lossy_alpha_33x17", "regression/lossy_100x63"] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        let yuv = decoder.read_yuv().unwrap();
+        assert_send(&yuv);
+        let mut gray_alpha = vec![0; decoder.output_buffer_size_gray_alpha().unwrap()];
+        decoder.read_image_gray_alpha(&mut gray_alpha).unwrap();
+
+        let (width, height) = (yuv.width as usize, yuv.height as usize);
+        assert_eq!((yuv.width, yuv.height), decoder.dimensions());
+        assert_eq!(yuv.y_stride, width.div_ceil(16) * 16, "{file}");
+        assert_eq!(yuv.uv_stride, yuv.y_stride / 2, "{file}");
+        assert_eq!(
+            yuv.y.len(),
+            yuv.y_stride * height.div_ceil(16) * 16,
+            "{file}"
+        );
+        assert_eq!(yuv.u.len(), yuv.y.len() / 4, "{file}");
+        assert_eq!(yuv.v.len(), yuv.y.len() / 4, "{file}");
+        assert_eq!(yuv.alpha.is_some(), decoder.has_alpha(), "{file}");
+
+        for (y, row) in gray_alpha.chunks_exact(width * 2).enumerate() {
+            for (x, gray_alpha) in row.chunks_exact(2).enumerate() {
+                assert_eq!(gray_alpha[0], yuv.y[y * yuv.y_stride + x], "{file}");
+                if let Some(alpha) = &yuv.alpha {
+                    assert_eq!(gray_alpha[1], alpha[y * width + x], "{file}");
+                }
+            }
+        }
+    }
+
+    for file in ["gallery2/1_webp_ll", "animated/random_lossy"] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        assert!(matches!(
+            decoder.read_yuv(),
+            Err(image_webp::DecodingError::InvalidParameter(_))
+        ));
+    }
+}
+
+#[test]
+fn animation_seek_frame() {
+    let contents = std::fs::read("tests/images/animated/random_lossy.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+
+    let mut frames = Vec::new();
+    for _ in 0..decoder.num_frames() {
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_frame(&mut data).unwrap();
+        frames.push(data);
+    }
+
+    let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+    for index in [2, 1, 3, 0, 0] {
+        decoder.seek_frame(index).unwrap();
+        decoder.read_frame(&mut data).unwrap();
+        assert_eq!(data, frames[index as usize], "frame {index}");
+    }
+
+    // Seeking past the end fails and keeps the position.
+    assert!(matches!(
+        decoder.seek_frame(decoder.num_frames()),
+        Err(image_webp::DecodingError::NoMoreFrames)
+    ));
+    decoder.read_frame(&mut data).unwrap();
+    assert_eq!(data, frames[1]);
+}
+
+#[test]
+fn animation_timing() {
+    let contents = std::fs::read("tests/images/animated/random_lossy.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    assert_eq!(decoder.loop_count(), image_webp::LoopCount::Forever);
+    assert_eq!(decoder.background_color_hint(), Some([255, 255, 255, 255]));
+    assert_eq!(decoder.num_frames(), 4);
+    assert_eq!(decoder.loop_duration(), 600);
+    assert_eq!(
+        decoder.animation_params(),
+        Some(image_webp::AnimationParams {
+            canvas_width: decoder.dimensions().0,
+            canvas_height: decoder.dimensions().1,
+            background_color: [255, 255, 255, 255],
+            loop_count: image_webp::LoopCount::Forever,
+        })
+    );
+
+    let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+    for _ in 0..decoder.num_frames() {
+        assert_eq!(decoder.read_frame(&mut data).unwrap(), 150);
+    }
+}
+
+#[test]
+fn partition_size_exceeds_chunk() {
+    let mut contents = std::fs::read("tests/images/regression/lossy_8_partitions.webp").unwrap();
+
+    // Overwrite the size of the first partition in the table following the first partition.
+    let tag = u32::from_le_bytes([contents[20], contents[21], contents[22], 0]);
+    let first_partition_size = (tag >> 5) as usize;
+    let partition_sizes = 20 + 10 + first_partition_size;
+    contents[partition_sizes..][..3].copy_from_slice(&[0xff, 0xff, 0xff]);
+
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+    assert!(matches!(
+        decoder.read_image(&mut data),
+        Err(image_webp::DecodingError::BitStreamError)
+    ));
+}
+
+#[test]
+fn first_partition_size_exceeds_chunk() {
+    let mut contents = std::fs::read("tests/images/gallery1/1.webp").unwrap();
+
+    // Set the size of the first partition in the frame tag to its maximum.
+    contents[20] |= 0xe0;
+    contents[21..23].copy_from_slice(&[0xff, 0xff]);
+
+    // This looks like truncated data, which is reported as such rather than read out of bounds.
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents.clone())).unwrap();
+    let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+    assert!(matches!(
+        decoder.read_image(&mut data),
+        Err(image_webp::DecodingError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+    ));
+
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    decoder.set_error_concealment(true);
+    // With error concealment, the first partition takes up the rest of the data instead.
+    decoder.read_image(&mut data).unwrap();
+    assert!(decoder.is_incomplete());
+}
+
+#[test]
+fn decode_lossy_image_twice() {
+    // The second decode reuses the buffers of the first one.
+    let contents = std::fs::read("tests/images/gallery1/2.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+
+    let mut first = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.read_image(&mut first).unwrap();
+    let mut second = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.read_image(&mut second).unwrap();
+    assert_eq!(first, second);
+}
+
+#[test]
+fn truncated_images_return_error() {
+    for file in [
+        "gallery1/1",
+        "gallery2/1_webp_ll",
+        "gallery2/1_webp_a",
+        "animated/random_lossy",
+        "regression/lossy_8_partitions",
+    ] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let len = contents.len();
+
+        // The arithmetic decoder may read a couple of bytes past the end of a partition, so only
+        // truncations beyond that must fail.
+        for cut in [7, 64, 1000, len / 2, len - 30] {
+            let result = image_webp::WebPDecoder::new(Cursor::new(&contents[..len - cut]))
+                .and_then(|mut decoder| {
+                    let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+                    if decoder.is_animated() {
+                        for _ in 0..decoder.num_frames() {
+                            decoder.read_frame(&mut data)?;
+                        }
+                        Ok(())
+                    } else {
+                        decoder.read_image(&mut data)
+                    }
+                });
+            assert!(result.is_err(), "{file} truncated by {cut} bytes decoded");
+        }
+    }
+}
+
+#[test]
+fn truncated_lossy_image_is_concealed() {
+    let contents = std::fs::read("tests/images/gallery1/1.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(&contents)).unwrap();
+    let (width, height) = decoder.dimensions();
+    let mut full = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.read_image(&mut full).unwrap();
+    assert!(!decoder.is_incomplete());
+
+    let mut options = image_webp::WebPDecodeOptions::default();
+    options.error_concealment = true;
+    let truncated = Cursor::new(&contents[..contents.len() / 2]);
+    let mut decoder = image_webp::WebPDecoder::new_with_options(truncated, options).unwrap();
+    let mut data = vec![0; full.len()];
+    decoder.read_image(&mut data).unwrap();
+    assert!(decoder.is_incomplete());
+
+    // The top macroblock row decodes as usual, the bottom one is a uniform gray.
+    let stride = width as usize * 3;
+    assert_eq!(data[..16 * stride], full[..16 * stride]);
+    let last_row = &data[(height as usize - 1) * stride..];
+    assert!(last_row.chunks_exact(3).all(|p| p == &last_row[..3]));
+    assert!(last_row[..3].iter().all(|&c| c.abs_diff(128) < 8));
+}
+
+#[test]
+fn truncated_lossless_image_is_not_concealed() {
+    // Error concealment only applies to lossy images, so the RIFF chunk must still fit.
+    let contents = std::fs::read("tests/images/gallery2/1_webp_ll.webp").unwrap();
+    let mut options = image_webp::WebPDecodeOptions::default();
+    options.error_concealment = true;
+    let truncated = Cursor::new(&contents[..contents.len() / 2]);
+    assert!(matches!(
+        image_webp::WebPDecoder::new_with_options(truncated, options),
+        Err(image_webp::DecodingError::InvalidChunkSize)
+    ));
+}
+
+#[test]
+fn read_region_matches_full_image() {
+    for file in ["gallery1/1", "gallery2/1_webp_a"] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        let (width, height) = decoder.dimensions();
+        let bytes_per_pixel = if decoder.has_alpha() { 4 } else { 3 };
+
+        let mut image = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut image).unwrap();
+
+        let (x, y, w, h) = (17, 5, 40, 23);
+        let mut region = vec![0; w * h * bytes_per_pixel];
+        decoder
+            .read_region(x as u32, y as u32, w as u32, h as u32, &mut region)
+            .unwrap();
+        for row in 0..h {
+            let start = ((y + row) * width as usize + x) * bytes_per_pixel;
+            assert_eq!(
+                region[row * w * bytes_per_pixel..][..w * bytes_per_pixel],
+                image[start..][..w * bytes_per_pixel]
+            );
+        }
+
+        assert!(matches!(
+            decoder.read_region(width - 10, 0, 11, 1, &mut vec![0; 11 * bytes_per_pixel]),
+            Err(image_webp::DecodingError::FrameOutsideImage)
+        ));
+        assert!(matches!(
+            decoder.read_region(0, height, 1, 1, &mut vec![0; bytes_per_pixel]),
+            Err(image_webp::DecodingError::FrameOutsideImage)
+        ));
+    }
+}
+
+/// Returns `contents` as an extended file with an EXIF chunk holding `orientation`.
+fn with_exif_orientation(contents: &[u8], orientation: u8) -> Vec<u8> {
+    let mut webp = if &contents[12..16] == b"VP8X" {
+        contents[8..].to_vec()
+    } else {
+        let decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        let (width, height) = decoder.dimensions();
+        let mut webp = b"WEBPVP8X\x0a\0\0\0\0\0\0\0".to_vec();
+        if decoder.has_alpha() {
+            webp[12] |= 0x10;
+        }
+        webp.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        webp.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        webp.extend_from_slice(&contents[12..]);
+        webp
+    };
+    webp[12] |= 0x08;
+    webp.extend_from_slice(b"EXIF\x1a\0\0\0II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0");
+    webp.extend_from_slice(&[orientation, 0, 0, 0]);
+
+    let mut riff = b"RIFF".to_vec();
+    riff.extend_from_slice(&(webp.len() as u32).to_le_bytes());
+    riff.extend_from_slice(&webp);
+    riff
+}
+
+#[test]
+fn read_region_of_oriented_image() {
+    use image_webp::UpsamplingMethod;
+
+    let fancy = (UpsamplingMethod::Bilinear, "fancy");
+    let simple = (UpsamplingMethod::Simple, "simple");
+    // Lossless images are decoded in full for every region, so only read them once
+    for (file, upsamplings) in [
+        ("regression/lossy_alpha_33x17", &[fancy, simple][..]),
+        ("gallery1/1", &[fancy, simple]),
+        ("gallery2/1_webp_ll", &[fancy]),
+    ] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        for (orientation, &(upsampling, name)) in
+            (1..=8).flat_map(|o| upsamplings.iter().map(move |u| (o, u)))
+        {
+            let rotated = with_exif_orientation(&contents, orientation);
+            let mut decoder = image_webp::WebPDecoder::new(Cursor::new(rotated)).unwrap();
+            decoder.set_auto_orient(true);
+            decoder.set_lossy_upsampling(upsampling);
+            decoder.set_premultiply_alpha(true);
+            let (width, height) = decoder.dimensions();
+            let (width, height) = (width as usize, height as usize);
+            let bytes_per_pixel = if decoder.has_alpha() { 4 } else { 3 };
+            let mut image = vec![0; decoder.output_buffer_size().unwrap()];
+            decoder.read_image(&mut image).unwrap();
+
+            for (x, y, w, h) in [
+                (0, 0, width, height),
+                (1, 1, width - 2, height - 2),
+                (5, 3, 7, 4),
+                (2, 0, 1, height),
+                (width - 3, height - 1, 3, 1),
+            ] {
+                let mut region = vec![0; w * h * bytes_per_pixel];
+                decoder
+                    .read_region(x as u32, y as u32, w as u32, h as u32, &mut region)
+                    .unwrap();
+                for row in 0..h {
+                    let start = ((y + row) * width + x) * bytes_per_pixel;
+                    assert!(
+                        region[row * w * bytes_per_pixel..][..w * bytes_per_pixel]
+                            == image[start..][..w * bytes_per_pixel],
+                        "{file}: orientation {orientation}, {name} upsampling, region ({x}, {y}, {w}, {h}), row {row}"
+                    );
+                }
+            }
+
+            assert!(matches!(
+                decoder.read_region(0, height as u32, 1, 1, &mut vec![0; bytes_per_pixel]),
+                Err(image_webp::DecodingError::FrameOutsideImage)
+            ));
+        }
+    }
+}
+
+#[test]
+fn read_image_scaled_averages_blocks() {
+    let contents = std::fs::read("tests/images/gallery2/1_webp_a.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    let (width, height) = decoder.dimensions();
+
+    let mut image = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.read_image(&mut image).unwrap();
+
+    for (scale, divisor) in [
+        (image_webp::ScaleFactor::Half, 2),
+        (image_webp::ScaleFactor::Quarter, 4),
+        (image_webp::ScaleFactor::Eighth, 8),
+    ] {
+        let (scaled_width, scaled_height) = decoder.scaled_dimensions(scale);
+        assert_eq!(scaled_width, width.div_ceil(divisor));
+        assert_eq!(scaled_height, height.div_ceil(divisor));
+
+        let mut scaled = vec![0; scaled_width as usize * scaled_height as usize * 4];
+        decoder.read_image_scaled(scale, &mut scaled).unwrap();
+
+        // Check the center pixel against the average of its block, with colors weighted by
+        // alpha
+        let divisor = divisor as usize;
+        let (block_x, block_y) = (scaled_width as usize / 2, scaled_height as usize / 2);
+        let block = (0..divisor).flat_map(|y| {
+            (0..divisor)
+                .map(move |x| (block_y * divisor + y) * width as usize + block_x * divisor + x)
+        });
+        let alpha: u32 = block.clone().map(|i| u32::from(image[i * 4 + 3])).sum();
+        let pixel = &scaled[(block_y * scaled_width as usize + block_x) * 4..][..4];
+        for channel in 0..3 {
+            let sum: u32 = block
+                .clone()
+                .map(|i| u32::from(image[i * 4 + channel]) * u32::from(image[i * 4 + 3]))
+                .sum();
+            assert_eq!(u32::from(pixel[channel]), (sum + alpha / 2) / alpha);
+        }
+        let count = (divisor * divisor) as u32;
+        assert_eq!(u32::from(pixel[3]), (alpha + count / 2) / count);
+    }
+
+    assert!(matches!(
+        decoder.read_image_scaled(image_webp::ScaleFactor::Half, &mut []),
+        Err(image_webp::DecodingError::ImageTooLarge)
+    ));
+}
+
+#[test]
+fn read_image_scaled_of_oriented_image() {
+    let contents = std::fs::read("tests/images/regression/lossy_alpha_33x17.webp").unwrap();
+    for (orientation, premultiply) in (1..=8).flat_map(|o| [(o, false), (o, true)]) {
+        let rotated = with_exif_orientation(&contents, orientation);
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(rotated)).unwrap();
+        decoder.set_auto_orient(true);
+        let (width, height) = decoder.dimensions();
+        let (width, height) = (width as usize, height as usize);
+        let mut image = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut image).unwrap();
+
+        decoder.set_premultiply_alpha(premultiply);
+        let (scaled_width, scaled_height) =
+            decoder.scaled_dimensions(image_webp::ScaleFactor::Quarter);
+        assert_eq!(
+            (scaled_width, scaled_height),
+            (width.div_ceil(4) as u32, height.div_ceil(4) as u32)
+        );
+        let mut scaled = vec![0; scaled_width as usize * scaled_height as usize * 4];
+        decoder
+            .read_image_scaled(image_webp::ScaleFactor::Quarter, &mut scaled)
+            .unwrap();
+
+        // Average the blocks of the displayed image, which puts partial blocks at the right and
+        // bottom edges
+        let mut expected = Vec::new();
+        for block_y in 0..scaled_height as usize {
+            for block_x in 0..scaled_width as usize {
+                let pixels: Vec<&[u8]> = (block_y * 4..height.min(block_y * 4 + 4))
+                    .flat_map(|y| (block_x * 4..width.min(block_x * 4 + 4)).map(move |x| (x, y)))
+                    .map(|(x, y)| &image[(y * width + x) * 4..][..4])
+                    .collect();
+                let alpha: u32 = pixels.iter().map(|p| u32::from(p[3])).sum();
+                let count = pixels.len() as u32;
+                let mut pixel = [0; 4];
+                for channel in 0..3 {
+                    let sum: u32 = pixels
+                        .iter()
+                        .map(|p| u32::from(p[channel]) * u32::from(p[3]))
+                        .sum();
+                    pixel[channel] = (sum + alpha / 2).checked_div(alpha).unwrap_or(0);
+                }
+                pixel[3] = (alpha + count / 2) / count;
+                if premultiply {
+                    for channel in 0..3 {
+                        pixel[channel] = (pixel[channel] * pixel[3] + 127) / 255;
+                    }
+                }
+                expected.extend(pixel.map(|value| value as u8));
+            }
+        }
+        assert!(
+            scaled == expected,
+            "orientation {orientation}, premultiply {premultiply}"
+        );
+    }
+}
+
+#[test]
+fn output_options_apply_to_every_method() {
+    let contents = std::fs::read("tests/images/regression/lossy_alpha_33x17.webp").unwrap();
+    for (orientation, premultiply) in (1..=8).flat_map(|o| [(o, false), (o, true)]) {
+        let rotated = with_exif_orientation(&contents, orientation);
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(rotated)).unwrap();
+        decoder.set_auto_orient(true);
+        decoder.set_premultiply_alpha(premultiply);
+        let (width, height) = decoder.dimensions();
+        let (width, height) = (width as usize, height as usize);
+        let mut image = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut image).unwrap();
+        let message = format!("orientation {orientation}, premultiply {premultiply}");
+
+        let mut pixels = vec![[0; 4]; width * height];
+        decoder.read_image_pixels(&mut pixels).unwrap();
+        assert!(pixels.as_flattened() == image, "{message}");
+
+        let mut argb = vec![0; width * height];
+        decoder.read_image_argb_u32(&mut argb).unwrap();
+        assert!(
+            argb.iter()
+                .zip(image.chunks_exact(4))
+                .all(|(&argb, p)| argb == u32::from_be_bytes([p[3], p[0], p[1], p[2]])),
+            "{message}"
+        );
+
+        let mut streamed = vec![0; image.len()];
+        let mut rows = 0;
+        decoder
+            .read_image_streaming(&mut streamed, |_| rows += 1)
+            .unwrap();
+        assert!(streamed == image && rows == height, "{message}");
+
+        let mut progressed = vec![0; image.len()];
+        let mut fractions = Vec::new();
+        decoder
+            .read_image_with_progress(&mut progressed, |fraction| fractions.push(fraction))
+            .unwrap();
+        assert!(progressed == image, "{message}");
+        let expected: Vec<f32> = (16..height)
+            .step_by(16)
+            .map(|rows| rows as f32 / height as f32)
+            .chain([1.0])
+            .collect();
+        assert_eq!(fractions, expected, "{message}");
+
+        let mut written = Vec::new();
+        decoder.read_image_to_writer(&mut written).unwrap();
+        assert!(written == image, "{message}");
+
+        let mut top = vec![0; width * 5 * 4];
+        decoder.read_rows(5, &mut top).unwrap();
+        assert!(top == image[..top.len()], "{message}");
+
+        let mut tile = vec![0; 16 * 16 * 4];
+        decoder.read_tile(0, 0, 16, &mut tile).unwrap();
+        for (y, row) in tile.chunks_exact(16 * 4).enumerate() {
+            assert!(row == &image[y * width * 4..][..16 * 4], "{message}");
+        }
+
+        // The alpha of the gray image is oriented like that of the RGBA image
+        let mut gray_alpha = vec![0; decoder.output_buffer_size_gray_alpha().unwrap()];
+        decoder.read_image_gray_alpha(&mut gray_alpha).unwrap();
+        assert!(
+            gray_alpha
+                .chunks_exact(2)
+                .zip(image.chunks_exact(4))
+                .all(|(gray_alpha, pixel)| gray_alpha[1] == pixel[3]),
+            "{message}"
+        );
+
+        let yuv = decoder.read_yuv().unwrap();
+        assert_eq!((yuv.width, yuv.height), decoder.coded_dimensions());
+    }
+}
+
+#[test]
+fn lossy_clamping_type() {
+    let mut contents = std::fs::read("tests/images/gallery1/1.webp").unwrap();
+    let decode = |contents: &[u8]| {
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut data).unwrap();
+        (decoder.frame_header().unwrap(), data)
+    };
+    let (header, data) = decode(&contents);
+    assert_eq!(header.clamping_type, 0);
+
+    // The color space and clamping type are the first two flags of the first partition, which
+    // follows the 10 byte frame tag at offset 20. With a color space of 0, the clamping type is
+    // the second bit of the first byte, and setting it leaves the state of the arithmetic
+    // decoder unchanged for everything after it.
+    assert_eq!(&contents[12..16], b"VP8 ");
+    assert_eq!(contents[30] & 0xc0, 0);
+    contents[30] |= 0x40;
+
+    // No clamping is needed, so clamping anyway like libwebp gives the same pixels.
+    let (clamped_header, clamped_data) = decode(&contents);
+    assert_eq!(clamped_header.clamping_type, 1);
+    assert_eq!(clamped_header.quantizer_index, header.quantizer_index);
+    assert!(clamped_data == data);
+
+    contents[30] |= 0x80;
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(&contents)).unwrap();
+    let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+    assert!(matches!(
+        decoder.read_image(&mut data),
+        Err(image_webp::DecodingError::ColorSpaceInvalid(1))
+    ));
+}
+
+#[test]
+fn display_dimensions() {
+    let mut contents = std::fs::read("tests/images/gallery1/1.webp").unwrap();
+    let decoder = image_webp::WebPDecoder::new(Cursor::new(&contents)).unwrap();
+    assert_eq!(decoder.display_dimensions(), decoder.dimensions());
+    let (width, height) = decoder.dimensions();
+
+    // The scale is stored in the top two bits of the 16 bit width and height after the start
+    // code of the frame tag.
+    assert_eq!(&contents[23..26], [0x9d, 0x01, 0x2a]);
+    for (horizontal, vertical, display) in [
+        (1, 0, ((width * 5 + 2) / 4, height)),
+        (0, 2, (width, (height * 5 + 1) / 3)),
+        (3, 3, (width * 2, height * 2)),
+    ] {
+        contents[27] = contents[27] & 0x3f | horizontal << 6;
+        contents[29] = contents[29] & 0x3f | vertical << 6;
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(&contents)).unwrap();
+        assert_eq!(decoder.dimensions(), (width, height));
+        assert_eq!(decoder.coded_dimensions(), (width, height));
+        assert_eq!(decoder.display_dimensions(), display);
+
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut data).unwrap();
+        let header = decoder.frame_header().unwrap();
+        assert_eq!(
+            (header.horizontal_scale, header.vertical_scale),
+            (horizontal, vertical)
+        );
+    }
+}
+
+#[test]
+fn lossy_frame_header() {
+    fn decode_header(file: &str) -> image_webp::vp8::FrameHeader {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        assert!(decoder.frame_header().is_none());
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut data).unwrap();
+        decoder.frame_header().unwrap()
+    }
+
+    let header = decode_header("regression/lossy_8_partitions");
+    assert_eq!(header.num_partitions, 8);
+    assert_eq!(header.version, 0);
+    assert_eq!(header.clamping_type, 0);
+    assert!(header.for_display);
+
+    let header = decode_header("regression/lossy_simple_filter");
+    assert!(header.simple_filter);
+    assert_eq!(header.filter_level, 63);
+    assert_eq!(header.sharpness_level, 0);
+
+    let header = decode_header("regression/lossy_sharpness");
+    assert!(!header.simple_filter);
+    assert_eq!(header.sharpness_level, 6);
+
+    let header = decode_header("regression/lossy_segments");
+    assert!(header.segmentation_enabled);
+    assert_eq!(header.num_partitions, 1);
+    assert_eq!(header.prob_skip_false, None);
+
+    let header = decode_header("regression/lossy_skipped_macroblocks");
+    assert!(header.prob_skip_false.is_some());
+}
+
+#[test]
+fn read_image_pixels() {
+    let contents = std::fs::read("tests/images/gallery2/1_webp_a.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    let (width, height) = decoder.dimensions();
+
+    let mut bytes = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.read_image(&mut bytes).unwrap();
+    let mut pixels = vec![[0u8; 4]; width as usize * height as usize];
+    decoder.read_image_pixels(&mut pixels).unwrap();
+    assert_eq!(pixels.as_flattened(), bytes);
+
+    // Using RGB pixels for an image with alpha leaves the buffer short
+    let mut pixels = vec![[0u8; 3]; width as usize * height as usize];
+    assert!(matches!(
+        decoder.read_image_pixels(&mut pixels),
+        Err(image_webp::DecodingError::ImageTooLarge)
+    ));
+}
+
+#[test]
+fn decode_to_owned_buffer() {
+    for file in ["gallery1/1", "gallery2/1_webp_a"] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(&contents)).unwrap();
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut data).unwrap();
+
+        let (width, height, rgb) = image_webp::decode_rgb(&contents).unwrap();
+        assert_eq!((width, height), decoder.dimensions());
+        let (_, _, rgba) = image_webp::decode_rgba(&contents).unwrap();
+        assert_eq!(rgba.len(), width as usize * height as usize * 4);
+
+        for ((rgb, rgba), pixel) in rgb
+            .chunks_exact(3)
+            .zip(rgba.chunks_exact(4))
+            .zip(data.chunks_exact(if decoder.has_alpha() { 4 } else { 3 }))
+        {
+            assert_eq!(rgb, &pixel[..3]);
+            assert_eq!(&rgba[..3], &pixel[..3]);
+            assert_eq!(rgba[3], pixel.get(3).copied().unwrap_or(255));
+        }
+    }
+}
+
+#[test]
+fn peek_dimensions() {
+    for file in [
+        "gallery1/1",
+        "gallery2/1_webp_ll",
+        "gallery2/1_webp_a",
+        "animated/random_lossy",
+    ] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let (width, height, _) = image_webp::decode_rgba(&contents).unwrap();
+        assert_eq!(
+            image_webp::peek_dimensions(&contents).unwrap(),
+            (width, height),
+            "{file}"
+        );
+        assert!(image_webp::peek_dimensions(&contents[..20]).is_err());
+    }
+
+    // Only the headers are read, so neither the frames nor the EXIF orientation are looked at.
+    let contents = std::fs::read("tests/images/animated/random_lossy.webp").unwrap();
+    let (width, height, _) = image_webp::decode_rgba(&contents).unwrap();
+    assert!(image_webp::WebPDecoder::new(Cursor::new(&contents[..30])).is_err());
+    assert_eq!(
+        image_webp::peek_dimensions(&contents[..30]).unwrap(),
+        (width, height)
+    );
+
+    let contents = std::fs::read("tests/images/regression/lossy_alpha_33x17.webp").unwrap();
+    let contents = with_exif_orientation(&contents, 6);
+    assert_eq!(image_webp::peek_dimensions(&contents).unwrap(), (33, 17));
+}
+
+#[test]
+fn is_animated() {
+    for (file, animated) in [
+        ("gallery1/1", false),
+        ("gallery2/1_webp_ll", false),
+        ("gallery2/1_webp_a", false),
+        ("animated/random_lossy", true),
+        ("animated/random_lossless", true),
+    ] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        assert_eq!(
+            image_webp::is_animated(&contents).unwrap(),
+            animated,
+            "{file}"
+        );
+
+        // Only the headers before the first frame are needed.
+        assert_eq!(
+            image_webp::is_animated(&contents[..40]).unwrap(),
+            animated,
+            "{file}"
+        );
+        assert!(image_webp::is_animated(&contents[..12]).is_err());
+    }
+}
+
+#[test]
+fn read_image_streaming_reports_every_row() {
+    for file in ["gallery1/1", "gallery2/1_webp_a", "gallery2/1_webp_ll"] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        let (_, height) = decoder.dimensions();
+
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut data).unwrap();
+
+        let mut streamed = vec![0; decoder.output_buffer_size().unwrap()];
+        let mut rows = Vec::new();
+        decoder
+            .read_image_streaming(&mut streamed, |y| rows.push(y))
+            .unwrap();
+        assert_eq!(rows, (0..height as usize).collect::<Vec<_>>(), "{file}");
+        assert!(streamed == data, "{file}");
+    }
+}
+
+#[test]
+fn read_image_with_progress_reaches_one() {
+    let contents = std::fs::read("tests/images/gallery1/1.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    let mut full = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.read_image(&mut full).unwrap();
+
+    let mut data = vec![0; full.len()];
+    let mut fractions = Vec::new();
+    decoder
+        .read_image_with_progress(&mut data, |fraction| fractions.push(fraction))
+        .unwrap();
+    assert_eq!(data, full);
+
+    let (_, height) = decoder.dimensions();
+    assert_eq!(fractions.len(), height.div_ceil(16) as usize);
+    assert!(fractions.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(fractions.first(), Some(&(16.0 / height as f32)));
+    assert_eq!(fractions.last(), Some(&1.0));
+}
+
+#[test]
+fn read_image_to_writer_matches_read_image() {
+    for file in ["gallery1/1", "gallery2/1_webp_a", "gallery2/1_webp_ll"] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        decoder.set_premultiply_alpha(true);
+        let mut full = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut full).unwrap();
+
+        let mut written = Vec::new();
+        decoder.read_image_to_writer(&mut written).unwrap();
+        assert!(written == full, "{file}");
+    }
+
+    // Errors of the writer stop decoding
+    struct FailingWriter(usize);
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.0 < buf.len() {
+                return Err(std::io::ErrorKind::BrokenPipe.into());
+            }
+            self.0 -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let contents = std::fs::read("tests/images/gallery1/1.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    let mut writer = FailingWriter(10_000);
+    match decoder.read_image_to_writer(&mut writer) {
+        Err(image_webp::DecodingError::IoError(e)) => {
+            assert_eq!(e.kind(), std::io::ErrorKind::BrokenPipe)
+        }
+        result => panic!("unexpected result {result:?}"),
+    }
+}
+
+#[test]
+fn read_rows_matches_top_of_image() {
+    for file in ["gallery1/1", "gallery2/1_webp_a", "gallery2/1_webp_ll"] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        let (width, height) = decoder.dimensions();
+        let row_bytes = decoder.output_buffer_size().unwrap() / height as usize;
+
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut data).unwrap();
+
+        for rows in [1, 17, height as usize] {
+            let mut strip = vec![0; row_bytes * rows];
+            decoder.read_rows(rows, &mut strip).unwrap();
+            assert!(strip == data[..strip.len()], "{file} ({width}x{rows})");
+        }
+
+        let mut again = vec![0; data.len()];
+        decoder.read_image(&mut again).unwrap();
+        assert!(again == data, "{file}");
+
+        assert!(matches!(
+            decoder.read_rows(height as usize + 1, &mut []),
+            Err(image_webp::DecodingError::FrameOutsideImage)
+        ));
+        assert!(matches!(
+            decoder.read_rows(2, &mut vec![0; row_bytes]),
+            Err(image_webp::DecodingError::ImageTooLarge)
+        ));
+    }
+}
+
+#[test]
+fn truncated_headers_return_error() {
+    for file in [
+        "gallery1/1",
+        "gallery2/1_webp_ll",
+        "gallery2/1_webp_a",
+        "animated/random_lossy",
+    ] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        for len in 0..=40 {
+            let mut truncated = contents[..len].to_vec();
+            assert!(
+                image_webp::WebPDecoder::new(Cursor::new(&truncated)).is_err(),
+                "{file} truncated to {len} bytes"
+            );
+
+            // Also make the RIFF size match, so that parsing gets to the chunks inside it
+            if len >= 8 {
+                truncated[4..8].copy_from_slice(&(len as u32 - 8).to_le_bytes());
+                assert!(
+                    image_webp::WebPDecoder::new(Cursor::new(&truncated)).is_err(),
+                    "{file} truncated to {len} bytes with matching RIFF size"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn auto_orient_rotates_output() {
+    let contents = std::fs::read("tests/images/gallery1/1.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(&contents)).unwrap();
+    let (width, height) = decoder.dimensions();
+    let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.read_image(&mut data).unwrap();
+
+    // Move the VP8 chunk into an extended file with an EXIF orientation of 6 (rotate clockwise)
+    let exif = b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0";
+    let mut webp = b"WEBPVP8X\x0a\0\0\0\x08\0\0\0".to_vec();
+    webp.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    webp.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+    webp.extend_from_slice(&contents[12..]);
+    webp.extend_from_slice(b"EXIF");
+    webp.extend_from_slice(&(exif.len() as u32).to_le_bytes());
+    webp.extend_from_slice(exif);
+    let mut rotated = b"RIFF".to_vec();
+    rotated.extend_from_slice(&(webp.len() as u32).to_le_bytes());
+    rotated.extend_from_slice(&webp);
+
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(rotated)).unwrap();
+    let mut unrotated = vec![0; data.len()];
+    decoder.read_image(&mut unrotated).unwrap();
+    assert!(unrotated == data);
+
+    decoder.set_auto_orient(true);
+    assert_eq!(decoder.dimensions(), (height, width));
+    assert_eq!(decoder.display_dimensions(), (height, width));
+    assert_eq!(decoder.coded_dimensions(), (width, height));
+    let mut output = vec![0; data.len()];
+    decoder.read_image(&mut output).unwrap();
+    let (width, height) = (width as usize, height as usize);
+    for (x, y) in [
+        (0, 0),
+        (width - 1, 0),
+        (17, height - 1),
+        (width / 2, height / 3),
+    ] {
+        let (rotated_x, rotated_y) = (height - 1 - y, x);
+        assert_eq!(
+            output[(rotated_y * height + rotated_x) * 3..][..3],
+            data[(y * width + x) * 3..][..3]
+        );
+    }
+
+    decoder.set_auto_orient(false);
+    assert_eq!(decoder.dimensions(), (width as u32, height as u32));
+}
+
+#[test]
+fn prediction_modes() {
+    use image_webp::vp8::{IntraMode, LumaMode};
+
+    let contents = std::fs::read("tests/images/gallery1/1.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    let (width, height) = decoder.dimensions();
+    let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.read_image(&mut data).unwrap();
+    assert!(decoder.prediction_modes().is_empty());
+
+    decoder.set_macroblock_info(true);
+    decoder.read_image(&mut data).unwrap();
+    let modes = decoder.prediction_modes();
+    let (mb_width, mb_height) = decoder.macroblock_dimensions();
+    assert_eq!(
+        (mb_width, mb_height),
+        (width.div_ceil(16) as usize, height.div_ceil(16) as usize)
+    );
+    assert_eq!(modes.len(), mb_width * mb_height);
+    assert!(modes.iter().any(|mb| mb.luma_mode == LumaMode::B));
+    assert!(modes.iter().any(|mb| mb.luma_mode != LumaMode::B));
+    for mb in modes {
+        assert_eq!(mb.subblock_modes.is_some(), mb.luma_mode == LumaMode::B);
+    }
+    let subblock_modes: Vec<_> = modes
+        .iter()
+        .filter_map(|mb| mb.subblock_modes)
+        .flatten()
+        .collect();
+    assert!(subblock_modes.contains(&IntraMode::DC));
+    assert!(subblock_modes.iter().any(|&mode| mode != IntraMode::DC));
+}
+
+#[test]
+fn edge_prediction_modes() {
+    use image_webp::vp8::{ChromaMode, IntraMode, LumaMode};
+
+    // The lossy_edge_modes reference tests cover every prediction mode next to the synthetic
+    // borders above and to the left of the frame.
+    let (mut luma, mut chroma, mut subblocks) = (Vec::new(), Vec::new(), Vec::new());
+    let mut mode_pairs = Vec::new();
+    for i in 1..=6 {
+        let path = format!("tests/images/regression/lossy_edge_modes_{i}.webp");
+        let contents = std::fs::read(path).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        decoder.set_macroblock_info(true);
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut data).unwrap();
+
+        let (mb_width, _) = decoder.macroblock_dimensions();
+        for (i, mb) in decoder.prediction_modes().iter().enumerate() {
+            let (mbx, mby) = (i % mb_width, i / mb_width);
+            mode_pairs.push((mb.luma_mode, mb.chroma_mode));
+            if mbx > 0 && mby > 0 {
+                continue;
+            }
+            luma.push(mb.luma_mode);
+            chroma.push(mb.chroma_mode);
+            for (j, &mode) in mb.subblock_modes.iter().flatten().enumerate() {
+                if mbx == 0 && j % 4 == 0 || mby == 0 && j < 4 {
+                    subblocks.push(mode);
+                }
+            }
+        }
+    }
+
+    for mode in [
+        LumaMode::DC,
+        LumaMode::V,
+        LumaMode::H,
+        LumaMode::TM,
+        LumaMode::B,
+    ] {
+        assert!(luma.contains(&mode), "{mode:?}");
+    }
+    for mode in [ChromaMode::DC, ChromaMode::V, ChromaMode::H, ChromaMode::TM] {
+        assert!(chroma.contains(&mode), "{mode:?}");
+    }
+    // Chroma is predicted with its own mode, so each chroma mode also appears next to a different
+    // whole block luma mode.
+    for (chroma_mode, same_luma_mode) in [
+        (ChromaMode::DC, LumaMode::DC),
+        (ChromaMode::V, LumaMode::V),
+        (ChromaMode::H, LumaMode::H),
+        (ChromaMode::TM, LumaMode::TM),
+    ] {
+        assert!(
+            mode_pairs
+                .iter()
+                .any(|&(luma_mode, mode)| mode == chroma_mode
+                    && luma_mode != same_luma_mode
+                    && luma_mode != LumaMode::B),
+            "{chroma_mode:?}"
+        );
+    }
+    for mode in [
+        IntraMode::DC,
+        IntraMode::TM,
+        IntraMode::VE,
+        IntraMode::HE,
+        IntraMode::LD,
+        IntraMode::RD,
+        IntraMode::VR,
+        IntraMode::VL,
+        IntraMode::HD,
+        IntraMode::HU,
+    ] {
+        assert!(subblocks.contains(&mode), "{mode:?}");
+    }
+}
+
+#[test]
+fn macroblock_coefficients() {
+    let contents = std::fs::read("tests/images/regression/lossy_edge_modes_1.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.set_macroblock_info(true);
+    decoder.read_image(&mut data).unwrap();
+    assert!(decoder.macroblock_coefficients().is_none());
+
+    let modes = decoder.prediction_modes().to_vec();
+    let quantizers = decoder.quantizers().to_vec();
+    let mut non_zero = 0;
+    for (index, (mode, quant)) in modes.iter().zip(&quantizers).enumerate() {
+        decoder.set_macroblock_coefficients(Some(index));
+        decoder.read_image(&mut data).unwrap();
+        let coefficients = decoder.macroblock_coefficients().unwrap();
+
+        // The coefficients are dequantized with the step sizes of the macroblock.
+        let is_multiple = |block: &[i32; 16], dc: i16, ac: i16| {
+            block[0] % i32::from(dc) == 0 && block[1..].iter().all(|c| c % i32::from(ac) == 0)
+        };
+        match coefficients.y2 {
+            Some(y2) => {
+                assert_ne!(mode.luma_mode, image_webp::vp8::LumaMode::B);
+                assert!(is_multiple(&y2, quant.y2_dc, quant.y2_ac), "{index}");
+            }
+            None => assert_eq!(mode.luma_mode, image_webp::vp8::LumaMode::B),
+        }
+        for block in &coefficients.y {
+            let dc = if coefficients.y2.is_some() {
+                1
+            } else {
+                quant.y_dc
+            };
+            assert!(is_multiple(block, dc, quant.y_ac), "{index}");
+        }
+        for block in coefficients.u.iter().chain(&coefficients.v) {
+            assert!(is_multiple(block, quant.uv_dc, quant.uv_ac), "{index}");
+        }
+
+        let blocks = coefficients
+            .y
+            .iter()
+            .chain(&coefficients.u)
+            .chain(&coefficients.v);
+        non_zero += blocks.flatten().filter(|&&c| c != 0).count();
+    }
+    assert!(non_zero > 0);
+
+    decoder.set_macroblock_coefficients(Some(modes.len()));
+    decoder.read_image(&mut data).unwrap();
+    assert!(decoder.macroblock_coefficients().is_none());
+}
+
+#[test]
+fn quantizers_follow_segments() {
+    let contents = std::fs::read("tests/images/regression/lossy_segments.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    decoder.set_macroblock_info(true);
+    let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.read_image(&mut data).unwrap();
+    assert!(decoder.frame_header().unwrap().segmentation_enabled);
+
+    let quantizers = decoder.quantizers();
+    assert_eq!(quantizers.len(), decoder.prediction_modes().len());
+
+    // Macroblocks of the same segment share their quantizers, but the segments differ
+    let mut segments = std::collections::HashMap::new();
+    for quant in quantizers {
+        assert_eq!(*segments.entry(quant.segment).or_insert(*quant), *quant);
+    }
+    assert!(segments.len() > 1);
+    let mut steps: Vec<_> = segments.values().map(|quant| quant.y_ac).collect();
+    steps.dedup();
+    assert!(steps.len() > 1);
+}
+
+#[test]
+fn partitions_are_consumed() {
+    for file in ["gallery1/1", "regression/lossy_8_partitions"] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        decoder.set_macroblock_info(true);
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut data).unwrap();
+
+        // The encoder pads partitions by at most a byte. The 96 pixel high image only has 6
+        // macroblock rows, so its last two partitions hold just the 2 bytes of an empty stream.
+        let usage = decoder.partition_usage();
+        let num_partitions = decoder.frame_header().unwrap().num_partitions;
+        assert_eq!(usage.len(), 1 + usize::from(num_partitions), "{file}");
+        for &(consumed, size) in usage {
+            assert!(
+                consumed <= size && consumed + 2 >= size,
+                "{file}: {usage:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn partition_sizes() {
+    let contents = std::fs::read("tests/images/regression/lossy_8_partitions.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    assert_eq!(decoder.num_partitions(), 0);
+    let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.read_image(&mut data).unwrap();
+
+    assert_eq!(decoder.num_partitions(), 8);
+    assert_eq!(
+        decoder.num_partitions(),
+        decoder.frame_header().unwrap().num_partitions
+    );
+    // Without macroblock info the sizes are still known and match the recorded usage.
+    let sizes = decoder.partition_sizes().to_vec();
+    decoder.set_macroblock_info(true);
+    decoder.read_image(&mut data).unwrap();
+    let usage: Vec<usize> = decoder.partition_usage()[1..].iter().map(|u| u.1).collect();
+    assert_eq!(sizes, usage);
+}
+
+#[test]
+fn premultiplied_alpha() {
+    let contents = std::fs::read("tests/images/gallery2/1_webp_a.webp").unwrap();
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+    let mut straight = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.read_image(&mut straight).unwrap();
+
+    decoder.set_premultiply_alpha(true);
+    let mut premultiplied = vec![0; straight.len()];
+    decoder.read_image(&mut premultiplied).unwrap();
+
+    assert!(straight.chunks_exact(4).any(|p| p[3] != 0 && p[3] != 255));
+    for (s, p) in straight.chunks_exact(4).zip(premultiplied.chunks_exact(4)) {
+        let a = u16::from(s[3]);
+        let expected = s[..3]
+            .iter()
+            .map(|&c| ((u16::from(c) * a + 127) / 255) as u8);
+        assert!(expected.eq(p[..3].iter().copied()));
+        assert_eq!(s[3], p[3]);
+    }
+
+    let mut streamed = vec![0; straight.len()];
+    decoder.read_image_streaming(&mut streamed, |_| {}).unwrap();
+    assert!(streamed == premultiplied);
+    let mut progressed = vec![0; straight.len()];
+    decoder
+        .read_image_with_progress(&mut progressed, |_| {})
+        .unwrap();
+    assert!(progressed == premultiplied);
+}
+
+#[test]
+fn read_image_argb_u32() {
+    use image_webp::UpsamplingMethod;
+
+    for (file, upsampling) in [
+        ("gallery1/1", UpsamplingMethod::Bilinear),
+        ("gallery1/1", UpsamplingMethod::Simple),
+        ("gallery2/1_webp_a", UpsamplingMethod::Bilinear),
+        ("gallery2/1_webp_a", UpsamplingMethod::Simple),
+        ("gallery2/1_webp_ll", UpsamplingMethod::Bilinear),
+    ] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        decoder.set_lossy_upsampling(upsampling);
+        let (width, height) = decoder.dimensions();
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut data).unwrap();
+
+        let mut argb = vec![0; width as usize * height as usize];
+        decoder.read_image_argb_u32(&mut argb).unwrap();
+        let bytes_per_pixel = if decoder.has_alpha() { 4 } else { 3 };
+        for (&argb, pixel) in argb.iter().zip(data.chunks_exact(bytes_per_pixel)) {
+            let [a, r, g, b] = argb.to_be_bytes();
+            assert_eq!([r, g, b], pixel[..3]);
+            assert_eq!(a, pixel.get(3).copied().unwrap_or(255));
+        }
+
+        assert!(matches!(
+            decoder.read_image_argb_u32(&mut argb[1..]),
+            Err(image_webp::DecodingError::ImageTooLarge)
+        ));
+    }
+}
+
+#[test]
+fn read_tile_matches_full_image() {
+    // Lossless images are decoded in full for every tile, so use fewer of them
+    for (file, tile_sizes) in [
+        ("gallery1/1", &[112, 208][..]),
+        ("gallery2/1_webp_a", &[112]),
+        ("gallery2/1_webp_ll", &[256]),
+    ] {
+        let contents = std::fs::read(format!("tests/images/{file}.webp")).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(Cursor::new(contents)).unwrap();
+        let (width, height) = decoder.dimensions();
+        let (width, height) = (width as usize, height as usize);
+        let bytes_per_pixel = if decoder.has_alpha() { 4 } else { 3 };
+        let mut data = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut data).unwrap();
+
+        for &tile_size in tile_sizes {
+            for tile_y in 0..height.div_ceil(tile_size) {
+                for tile_x in 0..width.div_ceil(tile_size) {
+                    let (x0, y0) = (tile_x * tile_size, tile_y * tile_size);
+                    let tile_width = tile_size.min(width - x0);
+                    let tile_height = tile_size.min(height - y0);
+                    let mut tile = vec![0; tile_width * tile_height * bytes_per_pixel];
+                    decoder
+                        .read_tile(tile_x, tile_y, tile_size, &mut tile)
+                        .unwrap();
+
+                    for (y, row) in tile.chunks_exact(tile_width * bytes_per_pixel).enumerate() {
+                        let start = ((y0 + y) * width + x0) * bytes_per_pixel;
+                        assert!(
+                            row == &data[start..][..row.len()],
+                            "{file}: tile ({tile_x}, {tile_y}) of size {tile_size}, row {y}"
+                        );
+                    }
+                }
+            }
+        }
+
+        assert!(matches!(
+            decoder.read_tile(width.div_ceil(64), 0, 64, &mut []),
+            Err(image_webp::DecodingError::FrameOutsideImage)
+        ));
+        assert!(matches!(
+            decoder.read_tile(0, 0, 40, &mut []),
+            Err(image_webp::DecodingError::InvalidParameter(_))
+        ));
+    }
+}